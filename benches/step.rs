@@ -0,0 +1,140 @@
+//! `cargo bench --no-default-features --bench step`
+//!
+//! steps random graphs three ways: matching every pattern against the edges (`Ruleset::apply`),
+//! the same loop over every node with the compiled ruleset (`CompiledRuleset::apply`), and
+//! `Automaton::step`, which also only looks at nodes that can change, so it gets faster as the
+//! graph settles down. the first ratio is what compiling gains, the second adds the skipped
+//! nodes and the clock divider and topology bookkeeping the loops leave out. the small graph
+//! fits in cache, on the large one all of them are mostly waiting for memory.
+//!
+//! the electron states walk their edges at most twice, so the compiled ruleset leaves them to
+//! pattern matching and the first ratio stays around 1x: looking up a histogram costs more than
+//! two walks over four edges, and both wait on the same neighbours
+
+use std::time::{Duration, Instant};
+
+use nodular::compiled::CompiledRuleset;
+use nodular::rng::Rng;
+use nodular::{Automaton, Graph, Node, Ruleset, Vec2};
+
+const EDGES: usize = 4;
+const STEPS: usize = 20;
+
+// a cyclic automaton with a few more comparisons per state than the electron rules
+const CYCLIC: &str = "
+red: green >= 2 & blue < 3 -> green; green in {1, 5, 6} & yellow in {0, 2} -> yellow; blue > yellow -> blue; * -> red
+green: blue >= 2 & yellow < 3 -> blue; blue in {1, 5, 6} & red in {0, 2} -> red; yellow > red -> yellow; * -> green
+blue: yellow >= 2 & red < 3 -> yellow; yellow in {1, 5, 6} & green in {0, 2} -> green; red > green -> red; * -> blue
+yellow: red >= 2 & green < 3 -> red; red in {1, 5, 6} & blue in {0, 2} -> blue; green > blue -> green; * -> yellow
+";
+
+fn random_graph(nodes: usize, states: usize, background: u32, density: f64) -> Graph {
+    let rng = Rng::new(1, 0);
+    let mut graph = Graph::new();
+    for i in 0..nodes {
+        // `density` of the nodes get a random state, the others `background`
+        let state = if rng.roll(i, 0) < density {
+            (rng.roll(i, EDGES + 1) * states as f64) as u32
+        } else {
+            background
+        };
+        graph.add_node(Node::new(state, state, vec![], Vec2::zero()));
+    }
+    for i in 0..nodes {
+        for j in 0..EDGES {
+            graph.add_edge(i, (rng.roll(i, j + 1) * nodes as f64) as usize);
+        }
+    }
+    graph
+}
+
+/// one synchronous step of every node with `apply`
+fn apply_step(automaton: &mut Automaton, mut apply: impl FnMut(usize, &mut Graph, &Rng)) {
+    for node in automaton.graph.nodes.iter_mut() {
+        std::mem::swap(&mut node.read, &mut node.write);
+    }
+    let rng = Rng::new(automaton.seed, automaton.tick);
+    for node in 0..automaton.graph.nodes.len() {
+        apply(node, &mut automaton.graph, &rng);
+    }
+    automaton.tick += 1;
+}
+
+/// the mean time of `STEPS` steps of each, alternating so all see the same cache and clock
+/// conditions
+fn time(rules: &Ruleset, automatons: &mut [Automaton; 3]) -> [Duration; 3] {
+    let mut compiled = CompiledRuleset::new(rules);
+    let mut times = [Duration::ZERO; 3];
+    for _ in 0..STEPS {
+        let [naive, table, stepped] = automatons;
+        let start = Instant::now();
+        apply_step(naive, |node, graph, rng| {
+            rules.apply(node, graph, rng).unwrap();
+        });
+        times[0] += start.elapsed();
+
+        let start = Instant::now();
+        apply_step(table, |node, graph, rng| {
+            compiled.apply(node, graph, rng).unwrap();
+        });
+        times[1] += start.elapsed();
+
+        let start = Instant::now();
+        stepped.step();
+        times[2] += start.elapsed();
+    }
+    times.map(|a| a / STEPS as u32)
+}
+
+fn bench(name: &str, rules: &str, nodes: usize, background: u32, density: f64, threads: usize) {
+    let rules: Ruleset = rules.parse().unwrap();
+    let automaton = Automaton::new(
+        rules.clone(),
+        random_graph(nodes, rules.rules.len(), background, density),
+    );
+
+    // clones so all get the same compact memory layout
+    let mut automatons = [automaton.clone(), automaton.clone(), automaton];
+    automatons[2].threads = threads;
+    let [naive, compiled, stepped] = time(&rules, &mut automatons);
+
+    for other in &automatons[1..] {
+        assert!(
+            automatons[0]
+                .graph
+                .nodes
+                .iter()
+                .zip(&other.graph.nodes)
+                .all(|(a, b)| a.write == b.write),
+            "compiled step disagrees with the pattern matcher"
+        );
+    }
+
+    let ratio = |a: Duration| naive.as_secs_f64() / a.as_secs_f64();
+    println!(
+        "{}, {} nodes, {} threads: pattern matching {:?}, compiled {:?} ({:.2}x), \
+         Automaton::step {:?} ({:.2}x) per step",
+        name,
+        nodes,
+        threads,
+        naive,
+        compiled,
+        ratio(compiled),
+        stepped,
+        ratio(stepped)
+    );
+}
+
+fn main() {
+    println!("{} edges per node, mean of {} steps", EDGES, STEPS);
+    let electron = include_str!("../rules/electron.rules");
+    for nodes in [10_000, 100_000] {
+        bench("electron", electron, nodes, 0, 1.0, 1);
+        bench("cyclic", CYCLIC, nodes, 0, 1.0, 1);
+    }
+    // mostly wire, only the nodes near electrons have to be looked at
+    bench("quiet electron", electron, 100_000, 1, 0.01, 1);
+
+    let threads = std::thread::available_parallelism().map_or(1, |a| a.get());
+    bench("cyclic", CYCLIC, 100_000, 0, 1.0, threads);
+}
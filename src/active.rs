@@ -0,0 +1,100 @@
+//! Keeps track of which nodes can change on the next tick.
+//!
+//! A node whose state and neighbours' states stayed the same last tick would match the same
+//! rule again and keep its state, so `Automaton::step` only has to look at nodes that changed,
+//! their neighbours in both directions, and nodes that can change on their own (see
+//! `CompiledRuleset::is_restless`, clock divided nodes and pulse generators).
+
+use crate::compiled::CompiledRuleset;
+use crate::graph::{Direction, Graph};
+
+#[derive(Clone, Debug)]
+pub struct ActiveSet {
+    /// no duplicates, in no particular order
+    nodes: Vec<usize>,
+    /// `marked[i]` is whether `i` is in `nodes`
+    marked: Vec<bool>,
+    /// set once most nodes are in, tracking the rest costs more than looking at all of them
+    all: bool,
+    /// `Graph::edits` when the set was made, any edit since makes it useless
+    edits: u64,
+}
+
+impl ActiveSet {
+    pub fn new(graph: &Graph) -> Self {
+        Self {
+            nodes: vec![],
+            marked: vec![false; graph.nodes.len()],
+            all: false,
+            edits: graph.edits(),
+        }
+    }
+
+    /// whether the graph is still the one the set was made for
+    pub fn is_valid(&self, graph: &Graph) -> bool {
+        self.edits == graph.edits() && self.marked.len() == graph.nodes.len()
+    }
+
+    /// empties the set, giving back its nodes in index order
+    pub fn take(&mut self) -> Vec<usize> {
+        if self.all {
+            self.all = false;
+            return (0..self.marked.len()).collect();
+        }
+        let mut nodes = std::mem::take(&mut self.nodes);
+        // sorted for memory locality, past some size scanning the marks is cheaper
+        if nodes.len() > self.marked.len() / 8 {
+            nodes = (0..self.marked.len()).filter(|a| self.marked[*a]).collect();
+            self.marked.fill(false);
+        } else {
+            for &node in &nodes {
+                self.marked[node] = false;
+            }
+            nodes.sort_unstable();
+        }
+        nodes
+    }
+
+    /// adds the nodes that can change after a step that looked at `evaluated`,
+    /// every node not in there must have `read == write`
+    pub fn collect(&mut self, graph: &Graph, compiled: &CompiledRuleset, evaluated: &[usize]) {
+        for &node in evaluated {
+            // everything is in already
+            if self.all {
+                break;
+            }
+            let data = &graph.nodes[node];
+            if data.read != data.write {
+                self.wake(graph, node);
+            } else if data.is_restless() || compiled.is_restless(data.write) {
+                self.push(node);
+            }
+        }
+        self.edits = graph.edits();
+    }
+
+    /// adds `node` and its neighbours, for when the node was changed by hand
+    pub fn wake(&mut self, graph: &Graph, node: usize) {
+        if self.all {
+            return;
+        }
+        self.push(node);
+        for edge in graph.neighbours(node, Direction::Both) {
+            self.push(edge.to);
+        }
+    }
+
+    fn push(&mut self, node: usize) {
+        if self.all || self.marked[node] {
+            return;
+        }
+        self.marked[node] = true;
+        self.nodes.push(node);
+        if self.nodes.len() > self.marked.len() / 2 {
+            self.all = true;
+            for node in self.nodes.drain(..) {
+                self.marked[node] = false;
+            }
+        }
+    }
+}
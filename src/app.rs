@@ -1,19 +1,25 @@
-use midi_msg::MidiMsg;
 use raylib::{prelude::*, RaylibHandle, RaylibThread};
 use rfd::FileDialog;
 
-use crate::graph::{Graph, Node};
-use crate::{automaton::Automaton, vec2::Vec2};
 use midir::*;
-use std::borrow::Borrow;
-use std::ffi::{CStr, CString};
-use std::fmt::{Debug, Display};
+use nodular::automaton::{Automaton, Ruleset, UpdateMode};
+use nodular::component::{self, PortKind};
+use nodular::generate::Neighbourhood;
+use nodular::graph::{Edge, Graph, Node, NodeId, NodeKind};
+use nodular::note::{Accidental, Note, NoteLetter};
+use nodular::presets::PRESETS;
+use nodular::stats::RuleStats;
+use nodular::undo::Edit;
+use nodular::vec2::Vec2;
 use std::fs::{self, File};
 use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 enum Scene {
     Normal,
     MidiSelect,
+    NewWorld,
+    Insert,
 }
 
 pub struct App {
@@ -25,12 +31,17 @@ pub struct App {
     connection: Option<MidiOutputConnection>,
     scene: Scene,
     should_step: bool,
+    /// tick picked with "step back" or the history slider
+    seek_to: Option<u64>,
 }
 
 impl App {
-    pub fn new(automaton: Automaton) -> Self {
+    pub fn new(mut automaton: Automaton) -> Self {
+        automaton.detect_cycles(true);
+        automaton.keep_history(true);
+        // fits the buttons along the top and the left side
         let (rl, thread) = raylib::init()
-            .size(400, 800)
+            .size(1200, 900)
             .resizable()
             .msaa_4x()
             .title("nodular 2")
@@ -49,12 +60,31 @@ impl App {
             scene: Scene::Normal,
             connection: None,
             should_step: false,
+            seek_to: None,
         }
     }
 
     fn step(&mut self) {
         self.play_midi();
+        let edits = self.automaton.graph.edits();
         self.automaton.step();
+        self.rewritten(edits);
+    }
+
+    fn seek(&mut self, tick: u64) {
+        let edits = self.automaton.graph.edits();
+        self.automaton.seek(tick);
+        self.rewritten(edits);
+    }
+
+    /// rewriting rules may have added or removed nodes, indices aren't stable then even if
+    /// there are as many nodes as before. the selection holds ids so it keeps the nodes that
+    /// are still there
+    fn rewritten(&mut self, edits: u64) {
+        if self.automaton.graph.edits() != edits {
+            self.ui_state.dragging_node_positions = None;
+            self.ui_state.connecting_from = None;
+        }
     }
 
     pub fn run(&mut self) {
@@ -69,6 +99,9 @@ impl App {
                     } else if self.should_step {
                         self.step()
                     }
+                    if let Some(tick) = self.seek_to.take() {
+                        self.seek(tick)
+                    }
 
                     // pause unpuase
                     if self.rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
@@ -76,6 +109,7 @@ impl App {
                     }
                     // find coliding node
                     self.ui_state.hovering_over = None;
+                    let hidden = self.hidden();
                     for (i, position) in self
                         .automaton
                         .graph
@@ -84,24 +118,29 @@ impl App {
                         .map(|a| a.position)
                         .enumerate()
                     {
-                        if check_collision_point_circle(
-                            self.rl.get_screen_to_world2D(
-                                self.rl.get_mouse_position(),
-                                self.ui_state.camera,
-                            ),
-                            <Vec2 as Into<Vector2>>::into(position),
-                            30.0 * self.ui_state.camera.zoom,
-                        ) {
+                        if !hidden[i]
+                            && check_collision_point_circle(
+                                self.rl.get_screen_to_world2D(
+                                    self.rl.get_mouse_position(),
+                                    self.ui_state.camera,
+                                ),
+                                <Vec2 as Into<Vector2>>::into(position),
+                                30.0 * self.ui_state.camera.zoom,
+                            )
+                        {
                             self.ui_state.hovering_over = Some(i)
                         }
                     }
 
                     // add node
                     println!("{:?}", self.ui_state.selected_state);
-                    if self.rl.is_key_pressed(KeyboardKey::KEY_A) {
-                        self.automaton.graph.add_node(Node::new(
-                            self.ui_state.selected_state as u32,
-                            self.ui_state.selected_state as u32,
+                    let state = self.ui_state.state(self.automaton.rules());
+                    if let Some(state) =
+                        state.filter(|_| self.rl.is_key_pressed(KeyboardKey::KEY_A))
+                    {
+                        let id = self.automaton.graph.add_node(Node::new(
+                            state,
+                            state,
                             vec![],
                             self.rl
                                 .get_screen_to_world2D(
@@ -110,6 +149,19 @@ impl App {
                                 )
                                 .into(),
                         ));
+                        self.automaton.record(Edit::Remove(vec![id]));
+                    }
+
+                    // insert a generated graph at the mouse
+                    if self.rl.is_key_pressed(KeyboardKey::KEY_I) {
+                        self.ui_state.insert_at = self
+                            .rl
+                            .get_screen_to_world2D(
+                                self.rl.get_mouse_position(),
+                                self.ui_state.camera,
+                            )
+                            .into();
+                        self.scene = Scene::Insert;
                     }
 
                     // connect nodes
@@ -127,11 +179,16 @@ impl App {
                             (self.ui_state.hovering_over, self.ui_state.connecting_from)
                         {
                             if from != hovering {
-                                if self.automaton.graph.nodes[hovering].edges.contains(&from) {
-                                    self.automaton.graph.remove_edge(hovering, from);
-                                } else {
-                                    self.automaton.graph.add_edge(hovering, from);
-                                }
+                                let weight = match self.automaton.graph.edge_weight(hovering, from)
+                                {
+                                    Some(old) if old == self.ui_state.edge_weight => None,
+                                    _ => Some(self.ui_state.edge_weight),
+                                };
+                                self.automaton.edit(Edit::Edge {
+                                    from: self.automaton.graph.id(hovering),
+                                    to: self.automaton.graph.id(from),
+                                    weight,
+                                });
                             }
                         }
                         self.ui_state.connecting_from = None;
@@ -147,6 +204,7 @@ impl App {
                             .is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT)
                         {
                             if let Some(hovering) = self.ui_state.hovering_over {
+                                let hovering = self.automaton.graph.id(hovering);
                                 if self.rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
                                     if !self.ui_state.selected.contains(&hovering) {
                                         self.ui_state.selected.push(hovering)
@@ -154,17 +212,21 @@ impl App {
                                 } else {
                                     self.ui_state.selected = vec![hovering]
                                 }
+                                self.select_instances();
                             } else {
                             }
                         }
                     }
 
-                    // moving nodes
+                    // moving nodes, the whole drag is taken back at once
                     if self
                         .rl
                         .is_mouse_button_released(MouseButton::MOUSE_BUTTON_LEFT)
                     {
-                        self.ui_state.dragging_node_positions = None;
+                        if let Some(from) = self.ui_state.dragging_node_positions.take() {
+                            let selection = self.ui_state.selection(&self.automaton.graph);
+                            self.automaton.record_move(&selection, &from);
+                        }
                     }
 
                     if self
@@ -182,9 +244,9 @@ impl App {
                         );
                     }
                     if self.rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
-                        for selected in &self.ui_state.selected {
+                        for selected in self.ui_state.selection(&self.automaton.graph) {
                             if let Some(dragging) = self.ui_state.dragging_node_positions.clone() {
-                                self.automaton.graph.nodes[*selected].position = dragging[*selected]
+                                self.automaton.graph.nodes[selected].position = dragging[selected]
                                     + self
                                         .rl
                                         .get_screen_to_world2D(
@@ -208,19 +270,16 @@ impl App {
                     }
                     // deleting nodes
                     if self.rl.is_key_pressed(KeyboardKey::KEY_DELETE) {
-                        while let Some(node) = self.ui_state.selected.pop() {
-                            self.automaton
-                                .graph
-                                .remove_node_from_app(node, &mut self.ui_state);
-                        }
-                        self.ui_state.selected = vec![];
+                        let selected = std::mem::take(&mut self.ui_state.selected);
+                        self.automaton.edit(Edit::Remove(selected));
                     }
                     // changing state
-                    if self.rl.is_key_pressed(KeyboardKey::KEY_S) {
-                        for node in &self.ui_state.selected {
-                            self.automaton.graph.nodes[*node].write =
-                                self.ui_state.selected_state as u32
-                        }
+                    if let Some(state) =
+                        state.filter(|_| self.rl.is_key_pressed(KeyboardKey::KEY_S))
+                    {
+                        let selection = self.ui_state.selection(&self.automaton.graph);
+                        self.automaton
+                            .edit_nodes(&selection, |node| node.write = state);
                     }
                     // box select
                     if self.rl.get_mouse_x() > 100 {
@@ -258,9 +317,10 @@ impl App {
                                         && position.y >= y_1
                                         && position.y <= y_2
                                     {
-                                        self.ui_state.selected.push(i)
+                                        self.ui_state.selected.push(self.automaton.graph.id(i))
                                     }
                                 }
+                                self.select_instances();
                             }
                         }
                     }
@@ -289,7 +349,7 @@ impl App {
 
                     if self.rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL) {
                         if let Some(number) = self.rl.get_key_pressed_number() {
-                            if (number as i32) - 48 <= self.automaton.rules.names.len() as i32
+                            if (number as i32) - 48 <= self.automaton.rules().names.len() as i32
                                 && (number as i32 - 48) >= 0
                             {
                                 self.ui_state.selected_state = (number as i32) - 49
@@ -301,7 +361,8 @@ impl App {
                     if self.rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL)
                         && self.rl.is_key_pressed(KeyboardKey::KEY_C)
                     {
-                        let graph = Graph::copy(&self.automaton.graph, &self.ui_state.selected);
+                        let selection = self.ui_state.selection(&self.automaton.graph);
+                        let graph = Graph::copy(&self.automaton.graph, &selection);
                         self.clipboard = Some(graph);
 
                         println!("{:?}", self.clipboard);
@@ -317,21 +378,90 @@ impl App {
                                 let new_node = Node::new(
                                     node.read,
                                     node.write,
-                                    node.edges.iter().map(|a| a + len).collect(),
+                                    node.edges
+                                        .iter()
+                                        .map(|a| Edge::new(a.to + len, a.weight))
+                                        .collect(),
                                     node.position + Vec2::new(50.0, 50.0),
                                 );
 
-                                self.automaton.graph.add_node(new_node);
-                                self.ui_state
-                                    .selected
-                                    .push(self.automaton.graph.nodes.len() - 1)
+                                let id = self.automaton.graph.add_node(new_node);
+                                self.ui_state.selected.push(id)
                             }
+                            self.automaton.graph.rebuild_incoming();
+                            self.automaton
+                                .record(Edit::Remove(self.ui_state.selected.clone()));
                         }
                     }
+                    // undo, redo with shift
+                    if self.rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL)
+                        && self.rl.is_key_pressed(KeyboardKey::KEY_Z)
+                    {
+                        let edits = self.automaton.graph.edits();
+                        if self.rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                            self.automaton.redo();
+                        } else {
+                            self.automaton.undo();
+                        }
+                        self.rewritten(edits);
+                    }
                 }
                 Scene::MidiSelect => {
                     self.midi_select();
                 }
+                Scene::NewWorld => {
+                    self.new_world();
+                }
+                Scene::Insert => {
+                    self.insert();
+                }
+            }
+        }
+    }
+
+    /// whether `instance` of `component` is drawn as one node with its ports around it.
+    /// without ports there would be nothing to select or connect, those are drawn open
+    fn collapsed(&self, instance: u32, component: u32) -> bool {
+        Some(instance) != self.ui_state.open_instance
+            && !self.automaton.components[component as usize]
+                .ports
+                .is_empty()
+    }
+
+    /// nodes inside collapsed instances, only their ports are drawn
+    fn hidden(&self) -> Vec<bool> {
+        self.automaton
+            .graph
+            .nodes
+            .iter()
+            .map(|a| match a.member {
+                Some(member) if self.collapsed(member.instance, member.component) => {
+                    !self.automaton.components[member.component as usize]
+                        .is_port(member.node as usize)
+                }
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// adds the rest of every collapsed instance that has a node selected, so they move as one
+    fn select_instances(&mut self) {
+        let mut instances = vec![];
+        for node in self.ui_state.selection(&self.automaton.graph) {
+            if let Some(member) = self.automaton.graph.nodes[node].member {
+                if self.collapsed(member.instance, member.component)
+                    && !instances.contains(&member.instance)
+                {
+                    instances.push(member.instance);
+                }
+            }
+        }
+        for instance in instances {
+            for node in component::members(&self.automaton.graph, instance) {
+                let node = self.automaton.graph.id(node);
+                if !self.ui_state.selected.contains(&node) {
+                    self.ui_state.selected.push(node);
+                }
             }
         }
     }
@@ -376,6 +506,134 @@ impl App {
         }
     }
 
+    /// starts an empty world with one of the presets
+    fn new_world(&mut self) {
+        let mut d = self.rl.begin_drawing(&self.thread);
+        d.clear_background(Color::RAYWHITE);
+        if d.gui_button(rrect(0, 0, 100, 30), "back to app") {
+            self.scene = Scene::Normal;
+        }
+        let x = d.get_screen_width() / 2 - 300;
+        for (i, preset) in PRESETS.iter().enumerate() {
+            let y = 200 + i as i32 * 30;
+            d.gui_label(rrect(x + 210, y, 400, 30), preset.description);
+            if !d.gui_button(rrect(x, y, 200, 30), preset.name) {
+                continue;
+            }
+            match preset.ruleset() {
+                Ok(rules) => {
+                    let threads = self.automaton.threads;
+                    self.automaton = Automaton::new(rules, Graph::new());
+                    self.automaton.threads = threads;
+                    self.automaton.detect_cycles(true);
+                    self.automaton.keep_history(true);
+                    self.ui_state.selected = vec![];
+                    self.ui_state.dragging_node_positions = None;
+                    self.ui_state.connecting_from = None;
+                    self.ui_state.selected_state = 0;
+                    self.ui_state.error = None;
+                }
+                Err(err) => {
+                    self.ui_state.error = Some(format!("unable to parse {}: {}", preset.name, err))
+                }
+            }
+            self.scene = Scene::Normal;
+        }
+    }
+
+    /// generates a graph and adds it where the mouse was when the dialog opened
+    fn insert(&mut self) {
+        let mut d = self.rl.begin_drawing(&self.thread);
+        d.clear_background(Color::RAYWHITE);
+        if d.gui_button(rrect(0, 0, 100, 30), "back to app") {
+            self.scene = Scene::Normal;
+        }
+        let x = d.get_screen_width() / 2 - 300;
+        let ui = &mut self.ui_state;
+        if d.gui_spinner(
+            rrect(x, 120, 200, 30),
+            "",
+            &mut ui.insert_size,
+            0,
+            256,
+            ui.insert_size_edit_mode,
+        ) {
+            ui.insert_size_edit_mode = !ui.insert_size_edit_mode;
+        }
+        d.gui_label(rrect(x + 210, 120, 400, 30), "size, or children in a tree");
+        if d.gui_spinner(
+            rrect(x, 150, 200, 30),
+            "",
+            &mut ui.insert_height,
+            0,
+            64,
+            ui.insert_height_edit_mode,
+        ) {
+            ui.insert_height_edit_mode = !ui.insert_height_edit_mode;
+        }
+        d.gui_label(rrect(x + 210, 150, 400, 30), "height, or levels of a tree");
+        if d.gui_spinner(
+            rrect(x, 180, 200, 30),
+            "",
+            &mut ui.insert_percent,
+            0,
+            100,
+            ui.insert_percent_edit_mode,
+        ) {
+            ui.insert_percent_edit_mode = !ui.insert_percent_edit_mode;
+        }
+        d.gui_label(rrect(x + 210, 180, 400, 30), "edge or rewiring chance in %");
+
+        let size = ui.insert_size as usize;
+        let height = ui.insert_height as usize;
+        let chance = ui.insert_percent as f64 / 100.0;
+        let state = ui.state(self.automaton.rules()).unwrap_or(0);
+        // other random graphs every time something was added
+        let seed = self.automaton.seed ^ self.automaton.graph.nodes.len() as u64;
+        let generators = [
+            "line",
+            "ring",
+            "grid, 4 neighbours",
+            "grid, 8 neighbours",
+            "torus, 4 neighbours",
+            "torus, 8 neighbours",
+            "hexagons",
+            "random",
+            "small world",
+            "tree",
+        ];
+        for (i, name) in generators.iter().enumerate() {
+            if !d.gui_button(rrect(x, 230 + i as i32 * 30, 200, 30), name) {
+                continue;
+            }
+            let graph = match i {
+                0 => Graph::line(size, state),
+                1 => Graph::ring(size, state),
+                2 => Graph::grid(size, height, Neighbourhood::VonNeumann, state),
+                3 => Graph::grid(size, height, Neighbourhood::Moore, state),
+                4 => Graph::torus(size, height, Neighbourhood::VonNeumann, state),
+                5 => Graph::torus(size, height, Neighbourhood::Moore, state),
+                6 => Graph::hex(size, height, state),
+                7 => Graph::erdos_renyi(size, chance, seed, state),
+                8 => Graph::small_world(size, 2, chance, seed, state),
+                _ if size.checked_pow(height as u32).is_some_and(|a| a <= 10_000) => {
+                    Graph::tree(height, size, state)
+                }
+                _ => {
+                    self.ui_state.error = Some("that tree would have too many nodes".to_string());
+                    break;
+                }
+            };
+            let nodes: Vec<usize> = (0..graph.nodes.len()).collect();
+            let offset = self.ui_state.insert_at - component::middle(&graph, &nodes);
+            let nodes = self.automaton.graph.append(&graph, offset);
+            self.ui_state.select(&self.automaton.graph, &nodes);
+            self.automaton
+                .record(Edit::Remove(self.ui_state.selected.clone()));
+            self.scene = Scene::Normal;
+        }
+    }
+
     fn control_camera(&mut self) {
         self.ui_state.camera.offset = Vector2::new(
             self.rl.get_screen_width() as f32 / 2.0,
@@ -424,45 +682,153 @@ impl App {
             ));
         }
 
+        let hidden = self.hidden();
+        let instances: Vec<(String, Vector2)> = component::instances(&self.automaton.graph)
+            .into_iter()
+            .filter(|&(instance, component)| self.collapsed(instance, component))
+            .map(|(instance, component)| {
+                let members = component::members(&self.automaton.graph, instance);
+                let middle = component::middle(&self.automaton.graph, &members);
+                (
+                    self.automaton.components[component as usize].name.clone(),
+                    self.rl.get_world_to_screen2D(
+                        <Vec2 as Into<Vector2>>::into(middle),
+                        self.ui_state.camera,
+                    ),
+                )
+            })
+            .collect();
+
         let mut d = self.rl.begin_drawing(&self.thread);
         d.clear_background(Color::color_from_hsv(0.5, 0.1, 1.0));
 
+        // collapsed instances
+        for (name, position) in &instances {
+            d.draw_circle_v(
+                position,
+                45.0 * self.ui_state.camera.zoom,
+                Color::color_from_hsv(0.0, 0.0, 0.8),
+            );
+            d.draw_text(
+                name,
+                position.x as i32 - name.len() as i32 * 4,
+                position.y as i32 - 7,
+                15,
+                Color::BLACK,
+            );
+        }
+
         // render canvas
         //
         // selection_circles
-        for selected in &self.ui_state.selected {
+        let selection = self.ui_state.selection(&self.automaton.graph);
+        for selected in selection.into_iter().filter(|a| !hidden[*a]) {
             d.draw_circle_v(
-                node_positions[*selected],
+                node_positions[selected],
                 32.0 * self.ui_state.camera.zoom,
                 Color::BLACK,
             )
         }
 
         // nodes
-        for (node, position) in self
+        for ((node, position), _) in self
             .automaton
             .graph
             .nodes
             .iter()
-            .map(|a| a.write)
             .zip(&node_positions)
+            .zip(&hidden)
+            .filter(|a| !a.1)
         {
             d.draw_circle_v(
                 position,
                 30.0 * self.ui_state.camera.zoom,
-                Color::color_from_hsv(distribute_hue(node), 0.5, 0.90),
-            )
+                Color::color_from_hsv(distribute_hue(node.write), 0.5, 0.90),
+            );
+            if node.pinned {
+                d.draw_circle_lines(
+                    position.x as i32,
+                    position.y as i32,
+                    26.0 * self.ui_state.camera.zoom,
+                    Color::BLACK,
+                );
+            }
+            if let NodeKind::Pulse { every, .. } = node.kind {
+                let text = format!("~{}", every);
+                d.draw_text(
+                    &text,
+                    position.x as i32 - text.len() as i32 * 4,
+                    position.y as i32 - 22,
+                    15,
+                    Color::BLACK,
+                )
+            }
+            // which way a port of a collapsed instance connects
+            let port = node
+                .member
+                .filter(|a| Some(a.instance) != self.ui_state.open_instance)
+                .and_then(|a| {
+                    self.automaton.components[a.component as usize].port(a.node as usize)
+                });
+            if let Some(port) = port {
+                let text = match port {
+                    PortKind::Input => "in",
+                    PortKind::Output => "out",
+                    PortKind::Both => "in/out",
+                };
+                d.draw_text(
+                    text,
+                    position.x as i32 - text.len() as i32 * 4,
+                    position.y as i32 + 8,
+                    15,
+                    Color::BLACK,
+                )
+            }
+            // clock divider
+            if node.period > 1 {
+                let text = if node.phase > 0 {
+                    format!("/{}+{}", node.period, node.phase)
+                } else {
+                    format!("/{}", node.period)
+                };
+                d.draw_text(
+                    &text,
+                    position.x as i32 - text.len() as i32 * 4,
+                    position.y as i32 - 7,
+                    15,
+                    Color::BLACK,
+                )
+            }
         }
         // connections
         for i in 0..self.automaton.graph.nodes.len() {
             for edge in &self.automaton.graph.nodes[i].edges {
+                if hidden[i] || hidden[edge.to] {
+                    continue;
+                }
+                // inhibitory edges are red
+                let color = if edge.weight < 0 {
+                    Color::RED
+                } else {
+                    Color::BLACK
+                };
                 draw_spring_arrow(
                     &mut d,
-                    node_positions[*edge].into(),
+                    node_positions[edge.to].into(),
                     node_positions[i].into(),
-                    Color::BLACK,
+                    color,
                     30.0 * self.ui_state.camera.zoom,
-                )
+                );
+                if edge.weight != 1 {
+                    let middle = (node_positions[edge.to] + node_positions[i]) / 2.0;
+                    d.draw_text(
+                        &edge.weight.to_string(),
+                        middle.x as i32,
+                        middle.y as i32,
+                        15,
+                        color,
+                    )
+                }
             }
         }
 
@@ -476,14 +842,10 @@ impl App {
         d.gui_panel(rrect(0, 0, 100, height), "");
         d.gui_panel(rrect(0, 0, _width, 30), "");
 
-        d.gui_check_box(
-            rrect(10, 10, 10, 10),
-            "playing",
-            &mut self.ui_state.playing,
-        );
+        d.gui_check_box(rrect(10, 10, 10, 10), "playing", &mut self.ui_state.playing);
 
         let mut strings = vec![];
-        for name in &self.automaton.rules.names {
+        for name in &self.automaton.rules().names {
             strings.push(name.clone());
         }
         if d.gui_button(rrect(0, 30, 100, 30), "step") {
@@ -502,10 +864,16 @@ impl App {
         if d.gui_button(rrect(100, 0, 100, 30), "open world") {
             if let Some(file) = FileDialog::new().pick_file() {
                 if let Ok(content) = fs::read_to_string(file) {
-                    if let Ok(deserialized) = serde_json::from_str(&content) {
-                        self.automaton = deserialized;
-                    } else {
-                        println!("unable to deserialize file")
+                    match Automaton::from_json(&content) {
+                        Ok(deserialized) => {
+                            self.automaton = deserialized;
+                            self.automaton.detect_cycles(true);
+                            self.automaton.keep_history(true);
+                            self.ui_state.error = None;
+                        }
+                        Err(err) => {
+                            self.ui_state.error = Some(format!("unable to load world: {}", err))
+                        }
                     }
                 } else {
                     println!("unable to read file")
@@ -517,7 +885,7 @@ impl App {
         if d.gui_button(rrect(200, 0, 100, 30), "save_world") {
             if let Some(file_choice) = FileDialog::new().save_file() {
                 if let Ok(mut file) = File::create(file_choice) {
-                    if let Ok(parsed) = serde_json::to_string_pretty(&self.automaton) {
+                    if let Ok(parsed) = self.automaton.to_json() {
                         file.write_all(parsed.as_bytes())
                             .unwrap_or_else(|_| println!("unable to write to file"));
                     } else {
@@ -534,6 +902,57 @@ impl App {
         if d.gui_button(rrect(300, 0, 100, 30), "Midi settings") {
             self.scene = Scene::MidiSelect;
         }
+        if d.gui_button(rrect(1050, 0, 100, 30), "new world") {
+            self.scene = Scene::NewWorld;
+        }
+        if d.gui_button(rrect(500, 0, 100, 30), "step back") {
+            self.seek_to = Some(self.automaton.tick.saturating_sub(1));
+        }
+        // scrubbing through the history, stepping from a past tick forgets the ticks after it
+        let (oldest, newest) = self.automaton.history();
+        let mut tick = self.automaton.tick as f32;
+        d.gui_slider(
+            rrect(640, 5, 300, 20),
+            &oldest.to_string(),
+            &newest.to_string(),
+            &mut tick,
+            oldest as f32,
+            newest.max(oldest + 1) as f32,
+        );
+        if tick.round() as u64 != self.automaton.tick {
+            self.seek_to = Some(tick.round() as u64);
+        }
+        if d.gui_button(rrect(400, 0, 100, 30), "open rules") {
+            if let Some(file) = FileDialog::new().pick_file() {
+                if let Ok(content) = fs::read_to_string(file) {
+                    match content.parse() {
+                        Ok(rules) => {
+                            // the nodes have to be in states the new rules know
+                            match self.automaton.set_rules(rules) {
+                                Ok(()) => {
+                                    if self.automaton.stats.is_some() {
+                                        self.automaton.stats =
+                                            Some(RuleStats::new(self.automaton.rules()));
+                                    }
+                                    self.ui_state.error = None;
+                                }
+                                Err(err) => {
+                                    self.ui_state.error =
+                                        Some(format!("unable to use rules: {}", err));
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            self.ui_state.error = Some(format!("unable to parse rules: {}", err))
+                        }
+                    }
+                } else {
+                    println!("unable to read file")
+                }
+            } else {
+                println!("unable to pick file")
+            }
+        }
 
         note_input_box(
             &mut d,
@@ -543,13 +962,319 @@ impl App {
         );
 
         if d.gui_button(rrect(40, 360, 60, 30), "note") {
-            for selected in &self.ui_state.selected {
-                self.automaton.graph.nodes[*selected].note = Some(self.ui_state.note.clone())
-            }
+            let selection = self.ui_state.selection(&self.automaton.graph);
+            let note = self.ui_state.note.clone();
+            self.automaton
+                .edit_nodes(&selection, |node| node.note = Some(note.clone()));
         }
         if d.gui_button(rrect(0, 390, 100, 30), "clear note") {
-            for selected in &self.ui_state.selected {
-                self.automaton.graph.nodes[*selected].note = None;
+            let selection = self.ui_state.selection(&self.automaton.graph);
+            self.automaton
+                .edit_nodes(&selection, |node| node.note = None);
+        }
+
+        // weight of new edges, dragging onto an edge with another weight changes it
+        if d.gui_spinner(
+            rrect(0, 420, 100, 30),
+            "",
+            &mut self.ui_state.edge_weight,
+            -99,
+            99,
+            self.ui_state.edge_weight_edit_mode,
+        ) {
+            self.ui_state.edge_weight_edit_mode = !self.ui_state.edge_weight_edit_mode;
+        }
+
+        if d.gui_button(rrect(0, 450, 100, 30), "reseed") {
+            self.automaton.seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|a| a.as_nanos() as u64)
+                .unwrap_or(0);
+            self.automaton.wake_all();
+        }
+        d.gui_label(
+            rrect(5, 480, 95, 30),
+            &format!("seed {}", self.automaton.seed % 100000),
+        );
+
+        // update scheme, clicking cycles through them
+        let update_label = match self.automaton.update {
+            UpdateMode::Synchronous => "synchronous",
+            UpdateMode::Sequential => "sequential",
+            UpdateMode::RandomOrder => "random order",
+            UpdateMode::Block { .. } => "blocks",
+        };
+        if d.gui_button(rrect(0, 510, 100, 30), update_label) {
+            self.automaton.update = match self.automaton.update {
+                UpdateMode::Synchronous => UpdateMode::Sequential,
+                UpdateMode::Sequential => UpdateMode::RandomOrder,
+                UpdateMode::RandomOrder => UpdateMode::Block { blocks: 2 },
+                UpdateMode::Block { .. } => UpdateMode::Synchronous,
+            };
+            self.automaton.wake_all();
+        }
+        // clock divider of the selected nodes
+        if d.gui_spinner(
+            rrect(0, 570, 50, 30),
+            "",
+            &mut self.ui_state.period,
+            1,
+            64,
+            self.ui_state.period_edit_mode,
+        ) {
+            self.ui_state.period_edit_mode = !self.ui_state.period_edit_mode;
+        }
+        if d.gui_spinner(
+            rrect(50, 570, 50, 30),
+            "",
+            &mut self.ui_state.phase,
+            0,
+            63,
+            self.ui_state.phase_edit_mode,
+        ) {
+            self.ui_state.phase_edit_mode = !self.ui_state.phase_edit_mode;
+        }
+        if d.gui_button(rrect(0, 600, 100, 30), "set divider") {
+            let selection = self.ui_state.selection(&self.automaton.graph);
+            let (period, phase) = (self.ui_state.period as u32, self.ui_state.phase as u32);
+            self.automaton.edit_nodes(&selection, |node| {
+                node.period = period;
+                node.phase = phase % node.period;
+            });
+        }
+
+        // threads used for stepping, same results but faster on big graphs
+        let mut threads = self.automaton.threads as i32;
+        if d.gui_spinner(
+            rrect(0, 630, 100, 30),
+            "",
+            &mut threads,
+            1,
+            64,
+            self.ui_state.threads_edit_mode,
+        ) {
+            self.ui_state.threads_edit_mode = !self.ui_state.threads_edit_mode;
+        }
+        self.automaton.threads = threads as usize;
+
+        // whether the states came back to ones from before
+        let (cycle, since) = match self.automaton.cycle() {
+            Some(cycle) if cycle.is_fixed_point() => (
+                "fixed point".to_string(),
+                format!("after {} steps", cycle.start),
+            ),
+            Some(cycle) => (
+                format!("cycle of period {}", cycle.period),
+                format!("after {} steps", cycle.start),
+            ),
+            None => ("no cycle".to_string(), String::new()),
+        };
+        d.gui_label(rrect(5, 660, 95, 20), &cycle);
+        d.gui_label(rrect(5, 680, 95, 20), &since);
+
+        // pinned nodes keep their state, pulse generators switch to the selected state every
+        // few ticks and back to the state they had
+        if d.gui_button(rrect(0, 710, 100, 30), "pin") {
+            let selection = self.ui_state.selection(&self.automaton.graph);
+            let pinned = selection
+                .iter()
+                .all(|a| self.automaton.graph.nodes[*a].pinned);
+            self.automaton
+                .edit_nodes(&selection, |node| node.pinned = !pinned);
+        }
+        if d.gui_spinner(
+            rrect(0, 740, 50, 30),
+            "",
+            &mut self.ui_state.pulse_every,
+            1,
+            256,
+            self.ui_state.pulse_every_edit_mode,
+        ) {
+            self.ui_state.pulse_every_edit_mode = !self.ui_state.pulse_every_edit_mode;
+        }
+        let state = self.ui_state.state(self.automaton.rules());
+        let pulse = d.gui_button(rrect(50, 740, 50, 30), "pulse");
+        if let Some(state) = state.filter(|_| pulse) {
+            let selection = self.ui_state.selection(&self.automaton.graph);
+            let every = self.ui_state.pulse_every as u32;
+            self.automaton.edit_nodes(&selection, |node| {
+                node.kind = match node.kind {
+                    NodeKind::Pulse { rest, .. } => {
+                        node.write = rest;
+                        NodeKind::Ruled
+                    }
+                    NodeKind::Ruled => NodeKind::Pulse {
+                        state,
+                        rest: node.write,
+                        every,
+                        phase: 0,
+                    },
+                };
+            });
+        }
+
+        // how often each rule fired since recording started, rules that never did in red
+        if d.gui_button(rrect(950, 0, 100, 30), "rule stats") {
+            self.automaton.stats = match self.automaton.stats {
+                Some(_) => None,
+                None => Some(RuleStats::new(self.automaton.rules())),
+            };
+        }
+        if let Some(stats) = &self.automaton.stats {
+            let rows = stats.rows(self.automaton.rules());
+            let x = _width - 360;
+            d.gui_panel(rrect(x, 30, 360, 60 + rows.len() as i32 * 20), "");
+            d.gui_label(
+                rrect(x + 5, 30, 350, 20),
+                &format!("{} steps, {} rules fired", stats.steps, stats.total()),
+            );
+            for (i, row) in rows.iter().enumerate() {
+                let color = if row.count == 0 {
+                    Color::RED
+                } else {
+                    Color::BLACK
+                };
+                d.draw_text(
+                    &format!("{:>8} {}: {}", row.count, row.state, row.text),
+                    x + 5,
+                    55 + i as i32 * 20,
+                    15,
+                    color,
+                );
+            }
+            let y = 60 + rows.len() as i32 * 20;
+            let export = if d.gui_button(rrect(x, y, 120, 30), "export json") {
+                stats
+                    .to_json(self.automaton.rules())
+                    .map_err(|a| a.to_string())
+                    .and_then(|a| save_text(&a))
+                    .err()
+            } else if d.gui_button(rrect(x + 120, y, 120, 30), "export csv") {
+                save_text(&stats.to_csv(self.automaton.rules())).err()
+            } else {
+                None
+            };
+            if export.is_some() {
+                self.ui_state.error = export;
+            }
+            if d.gui_button(rrect(x + 240, y, 120, 30), "reset") {
+                self.automaton.stats = Some(RuleStats::new(self.automaton.rules()));
+            }
+        }
+
+        // components: "define" makes one from the selection, "place" adds an instance of the one
+        // named below, "open" shows the insides of the selected instance for editing and
+        // "update" makes the open instance with the selected new nodes the new definition
+        if d.gui_button(rrect(0, 770, 50, 30), "define") {
+            let name = format!("component {}", self.automaton.components.len() + 1);
+            let selection = self.ui_state.selection(&self.automaton.graph);
+            match self
+                .automaton
+                .edit_world(|a| a.define_component(name, &selection))
+            {
+                Some(component) => self.ui_state.component = component,
+                None => {
+                    self.ui_state.error =
+                        Some("select nodes that aren't in a component yet".to_string())
+                }
+            }
+        }
+        if d.gui_button(rrect(50, 770, 50, 30), "place")
+            && self.ui_state.component < self.automaton.components.len()
+        {
+            let nodes = self
+                .automaton
+                .instantiate(self.ui_state.component, self.ui_state.camera.target.into());
+            self.ui_state.select(&self.automaton.graph, &nodes);
+            self.automaton
+                .record(Edit::Remove(self.ui_state.selected.clone()));
+        }
+        let component = self
+            .automaton
+            .components
+            .get(self.ui_state.component)
+            .map_or("no components", |a| a.name.as_str());
+        if d.gui_button(rrect(0, 800, 100, 30), component) && !self.automaton.components.is_empty()
+        {
+            self.ui_state.component =
+                (self.ui_state.component + 1) % self.automaton.components.len();
+        }
+        let open = if self.ui_state.open_instance.is_some() {
+            "close"
+        } else {
+            "open"
+        };
+        if d.gui_button(rrect(0, 830, 50, 30), open) {
+            self.ui_state.open_instance = match self.ui_state.open_instance {
+                Some(_) => None,
+                None => self
+                    .ui_state
+                    .selection(&self.automaton.graph)
+                    .into_iter()
+                    .find_map(|a| self.automaton.graph.nodes[a].member)
+                    .map(|a| a.instance),
+            };
+        }
+        // how the selected nodes of the open instance connect to the outside, clicking goes
+        // through input, output, both and no port
+        let ports: Vec<(u32, u32)> = self
+            .ui_state
+            .selection(&self.automaton.graph)
+            .into_iter()
+            .filter_map(|a| self.automaton.graph.nodes[a].member)
+            .filter(|a| Some(a.instance) == self.ui_state.open_instance)
+            .map(|a| (a.component, a.node))
+            .collect();
+        let port = ports.first().and_then(|&(component, node)| {
+            self.automaton.components[component as usize].port(node as usize)
+        });
+        let port_label = match port {
+            Some(PortKind::Input) => "input port",
+            Some(PortKind::Output) => "output port",
+            Some(PortKind::Both) => "in/out port",
+            None => "no port",
+        };
+        if d.gui_button(rrect(0, 860, 100, 30), port_label) && !ports.is_empty() {
+            let kind = match port {
+                None => Some(PortKind::Input),
+                Some(PortKind::Input) => Some(PortKind::Output),
+                Some(PortKind::Output) => Some(PortKind::Both),
+                Some(PortKind::Both) => None,
+            };
+            self.automaton.edit_world(|a| {
+                for &(component, node) in &ports {
+                    a.set_port(component as usize, node as usize, kind);
+                }
+            });
+        }
+        if d.gui_button(rrect(50, 830, 50, 30), "update") {
+            if let Some(instance) = self.ui_state.open_instance.take() {
+                let selection = self.ui_state.selection(&self.automaton.graph);
+                self.automaton
+                    .edit_world(|a| a.redefine_component(instance, &selection));
+                self.ui_state.selected = vec![];
+            }
+        }
+
+        if let Some(error) = &self.ui_state.error {
+            d.draw_text(error, 110, height - 30, 20, Color::RED);
+        }
+
+        if let UpdateMode::Block { blocks } = &mut self.automaton.update {
+            let mut value = *blocks as i32;
+            if d.gui_spinner(
+                rrect(0, 540, 100, 30),
+                "",
+                &mut value,
+                1,
+                64,
+                self.ui_state.blocks_edit_mode,
+            ) {
+                self.ui_state.blocks_edit_mode = !self.ui_state.blocks_edit_mode;
+            }
+            if *blocks != value as u32 {
+                *blocks = value as u32;
+                self.automaton.wake_all();
             }
         }
     }
@@ -579,16 +1304,61 @@ pub struct UiState {
     pub type_scroll: i32,
     pub connecting_from: Option<usize>,
     pub hovering_over: Option<usize>,
-    pub selected: Vec<usize>,
+    /// ids so the selection stays put when other nodes are removed
+    pub selected: Vec<NodeId>,
     pub click_position: Vec2,
     pub dragging_node_positions: Option<Vec<Vec2>>,
     pub box_select_corner: Option<Vec2>,
     pub selected_midi_value: i32,
     pub node_edit_mode: bool,
     pub note: Note,
+    pub edge_weight: i32,
+    pub edge_weight_edit_mode: bool,
+    pub blocks_edit_mode: bool,
+    pub period: i32,
+    pub phase: i32,
+    pub period_edit_mode: bool,
+    pub phase_edit_mode: bool,
+    pub threads_edit_mode: bool,
+    pub pulse_every: i32,
+    pub pulse_every_edit_mode: bool,
+    /// the component "place" adds
+    pub component: usize,
+    /// the instance drawn expanded for editing
+    pub open_instance: Option<u32>,
+    /// where the insert dialog puts the generated graph
+    pub insert_at: Vec2,
+    pub insert_size: i32,
+    pub insert_size_edit_mode: bool,
+    pub insert_height: i32,
+    pub insert_height_edit_mode: bool,
+    pub insert_percent: i32,
+    pub insert_percent_edit_mode: bool,
+    /// the last load that went wrong, shown until the next one works
+    pub error: Option<String>,
 }
 
 impl UiState {
+    /// where the selected nodes are now, without the ones that were removed
+    fn selection(&self, graph: &Graph) -> Vec<usize> {
+        self.selected
+            .iter()
+            .filter_map(|&a| graph.index(a))
+            .collect()
+    }
+
+    /// the state picked in the list, `None` if nothing is or the rules don't have it
+    fn state(&self, rules: &Ruleset) -> Option<u32> {
+        u32::try_from(self.selected_state)
+            .ok()
+            .filter(|&a| (a as usize) < rules.names.len())
+    }
+
+    /// selects `nodes` instead of what was selected
+    fn select(&mut self, graph: &Graph, nodes: &[usize]) {
+        self.selected = nodes.iter().map(|&a| graph.id(a)).collect();
+    }
+
     fn new(camera: Camera2D) -> Self {
         Self {
             camera,
@@ -608,10 +1378,38 @@ impl UiState {
                 accidental: Accidental::Neutral,
                 octave: 4,
             },
+            edge_weight: 1,
+            edge_weight_edit_mode: false,
+            blocks_edit_mode: false,
+            period: 1,
+            phase: 0,
+            period_edit_mode: false,
+            phase_edit_mode: false,
+            threads_edit_mode: false,
+            pulse_every: 4,
+            pulse_every_edit_mode: false,
+            component: 0,
+            open_instance: None,
+            insert_at: Vec2::zero(),
+            insert_size: 8,
+            insert_size_edit_mode: false,
+            insert_height: 8,
+            insert_height_edit_mode: false,
+            insert_percent: 10,
+            insert_percent_edit_mode: false,
+            error: None,
         }
     }
 }
 
+/// writes `text` to a file the user picks
+fn save_text(text: &str) -> Result<(), String> {
+    let file = FileDialog::new()
+        .save_file()
+        .ok_or("unable to pick file to create")?;
+    fs::write(file, text).map_err(|a| format!("unable to write to file: {}", a))
+}
+
 fn distribute_hue(index: u32) -> f32 {
     let golden_ratio_conjugate = 0.618033988749895;
 
@@ -671,99 +1469,6 @@ fn find_rect(corner_1: Vector2, corner_2: Vector2) -> Rectangle {
     )
 }
 
-#[derive(Clone, Debug, Copy, serde::Serialize, serde::Deserialize)]
-enum NoteLetter {
-    C = 0,
-    D = 2,
-    E = 4,
-    F = 5,
-    G = 7,
-    A = 9,
-    B = 11,
-}
-#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
-enum Accidental {
-    Flat = -1,
-    Neutral = 0,
-    Sharp = 1,
-}
-
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
-pub struct Note {
-    letter: NoteLetter,
-    accidental: Accidental,
-    octave: u8,
-}
-
-impl Display for Note {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let note = match self.letter {
-            NoteLetter::C => 'C',
-            NoteLetter::D => 'D',
-            NoteLetter::E => 'E',
-            NoteLetter::F => 'F',
-            NoteLetter::G => 'G',
-            NoteLetter::A => 'A',
-            NoteLetter::B => 'B',
-        };
-        write!(f, "{}", note)?;
-        match self.accidental {
-            Accidental::Flat => write!(f, "b")?,
-            Accidental::Neutral => (),
-            Accidental::Sharp => write!(f, "#")?,
-        };
-        write!(f, "{}", self.octave)?;
-        Ok(())
-    }
-}
-impl Debug for Note {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let note = match self.letter {
-            NoteLetter::C => 'C',
-            NoteLetter::D => 'D',
-            NoteLetter::E => 'E',
-            NoteLetter::F => 'F',
-            NoteLetter::G => 'G',
-            NoteLetter::A => 'A',
-            NoteLetter::B => 'B',
-        };
-        write!(f, "{}", note)?;
-        match self.accidental {
-            Accidental::Flat => write!(f, "b")?,
-            Accidental::Neutral => (),
-            Accidental::Sharp => write!(f, "#")?,
-        };
-        write!(f, "{}", self.octave)?;
-        Ok(())
-    }
-}
-
-impl Note {
-    fn to_midi_number(&self) -> u8 {
-        24 + self.letter as u8 + self.octave * 12
-    }
-    fn to_midi_on(&self) -> Vec<u8> {
-        MidiMsg::ChannelVoice {
-            channel: midi_msg::Channel::Ch1,
-            msg: midi_msg::ChannelVoiceMsg::NoteOn {
-                note: self.to_midi_number(),
-                velocity: 60,
-            },
-        }
-        .to_midi()
-    }
-    fn to_midi_off(&self) -> Vec<u8> {
-        MidiMsg::ChannelVoice {
-            channel: midi_msg::Channel::Ch1,
-            msg: midi_msg::ChannelVoiceMsg::NoteOff {
-                note: self.to_midi_number(),
-                velocity: 0,
-            },
-        }
-        .to_midi()
-    }
-}
-
 fn note_input_box(
     d: &mut RaylibDrawHandle,
     rect: impl Into<Rectangle>,
@@ -1,33 +1,872 @@
-use crate::{
-    app::App,
-    graph::{Graph, Node},
-};
+use std::fmt;
+
+use crate::active::ActiveSet;
+use crate::compiled::CompiledRuleset;
+use crate::component::{self, Component, Member, Port, PortKind};
+use crate::cycle::{Cycle, CycleDetector};
+use crate::graph::{Direction, Edge, Graph, Node};
+use crate::history::{Before, Diff, History, Rewrite};
+use crate::rng::Rng;
+use crate::stats::RuleStats;
+use crate::undo::UndoStack;
+use crate::vec2::Vec2;
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Automaton {
-    pub rules: Ruleset,
+    /// private so changing it goes through `set_rules`, which throws away what was worked
+    /// out from the old rules
+    rules: Ruleset,
     pub graph: Graph,
+    /// seed for stochastic rules
+    #[serde(default)]
+    pub seed: u64,
+    /// number of steps taken so far
+    #[serde(default)]
+    pub tick: u64,
+    #[serde(default)]
+    pub update: UpdateMode,
+    /// how many threads `step` matches rules on, the results are the same for any number
+    #[serde(skip, default = "default_threads")]
+    pub threads: usize,
+    /// how often each rule fired, counted while this is `Some`
+    #[serde(skip)]
+    pub stats: Option<RuleStats>,
+    /// subgraphs the graph has instances of, see `component`
+    #[serde(default)]
+    pub components: Vec<Component>,
+    /// the rules compiled for each thread `step` used, kept until the rules change
+    #[serde(skip)]
+    compiled: Vec<CompiledRuleset>,
+    /// the nodes the next synchronous step looks at, everything if `None`
+    #[serde(skip)]
+    active: Option<ActiveSet>,
+    /// only looked for after `detect_cycles`, hashing the states costs some time every step
+    #[serde(skip)]
+    cycles: Option<CycleDetector>,
+    /// only kept after `keep_history`
+    #[serde(skip)]
+    history: Option<History>,
+    /// edits that can be taken back, see `undo`
+    #[serde(skip)]
+    pub(crate) undo_stack: UndoStack,
+}
+
+fn default_threads() -> usize {
+    1
+}
+
+/// fewest nodes worth handing to another thread
+const MIN_CHUNK: usize = 4096;
+
+/// what the rules that fired left to do after the nodes are updated
+struct Fired<'a> {
+    /// `(node, state, rule)` for rules that change the topology
+    topology: Vec<(usize, u32, usize)>,
+    stats: Option<&'a mut RuleStats>,
+}
+
+/// updates `nodes` from their `read` states, matching on up to `threads` threads with one
+/// compiled ruleset each
+fn update_nodes(
+    compiled: &mut Vec<CompiledRuleset>,
+    graph: &mut Graph,
+    nodes: &[usize],
+    tick: u64,
+    rng: &Rng,
+    threads: usize,
+    fired: &mut Fired,
+) {
+    // `None` for nodes the clock divider holds back and nodes that don't follow the rules
+    let pick = |compiled: &mut CompiledRuleset, graph: &Graph, node: usize| {
+        let data = &graph.nodes[node];
+        (data.updates_at(tick) && data.forced_state(tick).is_none())
+            .then(|| compiled.matching_rule(node, graph, rng).unwrap())
+    };
+    let picked: Vec<Option<usize>> = if threads > 1 && nodes.len() >= 2 * MIN_CHUNK {
+        // every thread has its own tables, the results don't depend on them
+        let graph = &*graph;
+        let chunk = nodes.len().div_ceil(threads).max(MIN_CHUNK);
+        let chunks = nodes.len().div_ceil(chunk);
+        while compiled.len() < chunks {
+            compiled.push(CompiledRuleset::new(compiled[0].ruleset()));
+        }
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = nodes
+                .chunks(chunk)
+                .zip(compiled.iter_mut())
+                .map(|(chunk, compiled)| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|node| pick(compiled, graph, *node))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|a| a.join().unwrap())
+                .collect()
+        })
+    } else {
+        nodes
+            .iter()
+            .map(|node| pick(&mut compiled[0], graph, *node))
+            .collect()
+    };
+
+    let compiled = &compiled[0];
+    for (&node, rule) in nodes.iter().zip(picked) {
+        let data = &mut graph.nodes[node];
+        match rule {
+            Some(rule) => {
+                let state = data.read;
+                compiled.ruleset().rules[state as usize][rule].fire(data);
+                if let Some(stats) = &mut fired.stats {
+                    stats.record(state, rule);
+                }
+                if compiled.changes_topology(state, rule) {
+                    fired.topology.push((node, state, rule));
+                }
+            }
+            None if data.updates_at(tick) => {
+                data.write = data.forced_state(tick).unwrap_or(data.read)
+            }
+            None => data.write = data.read,
+        }
+    }
+}
+
+/// the order in which `Automaton::step` updates nodes.
+/// nodes in the same group see each other's old states, later groups see the new ones
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum UpdateMode {
+    /// every node at once
+    #[default]
+    Synchronous,
+    /// one node at a time in index order
+    Sequential,
+    /// one node at a time in an order shuffled every tick
+    RandomOrder,
+    /// node `i` is in group `i % blocks`, groups go in order
+    Block { blocks: u32 },
+}
+
+impl UpdateMode {
+    fn groups(&self, len: usize, rng: &Rng) -> Vec<Vec<usize>> {
+        match *self {
+            UpdateMode::Synchronous => vec![(0..len).collect()],
+            UpdateMode::Sequential => (0..len).map(|a| vec![a]).collect(),
+            UpdateMode::RandomOrder => {
+                let mut order: Vec<usize> = (0..len).collect();
+                rng.shuffle(&mut order);
+                order.into_iter().map(|a| vec![a]).collect()
+            }
+            UpdateMode::Block { blocks } => {
+                let blocks = blocks.max(1) as usize;
+                (0..blocks)
+                    .map(|block| (block..len).step_by(blocks).collect())
+                    .collect()
+            }
+        }
+    }
 }
 
 impl Automaton {
     pub fn new(rules: Ruleset, graph: Graph) -> Self {
-        Self { rules, graph }
+        Self {
+            rules,
+            graph,
+            seed: 0,
+            tick: 0,
+            update: UpdateMode::Synchronous,
+            threads: 1,
+            stats: None,
+            components: vec![],
+            compiled: vec![],
+            active: None,
+            cycles: None,
+            history: None,
+            undo_stack: UndoStack::default(),
+        }
     }
+
+    /// loads a world as saved by the editor,
+    /// rejecting rulesets and node states `step` couldn't use
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let automaton: Self = serde_json::from_str(json)?;
+        automaton.validate().map_err(serde::de::Error::custom)?;
+        Ok(automaton)
+    }
+
+    /// checks the ruleset, that every node is in one of its states and that edges and members
+    /// lead to nodes that exist
+    pub fn validate(&self) -> Result<(), RulesetError> {
+        self.rules.validate()?;
+        let states = self.rules.rules.len() as u32;
+        let len = self.graph.nodes.len();
+        for (node, data) in self.graph.nodes.iter().enumerate() {
+            if let Some(edge) = data
+                .edges
+                .iter()
+                .chain(&data.incoming)
+                .find(|a| a.to >= len)
+            {
+                return Err(RulesetError::EdgeOutOfRange { node, to: edge.to });
+            }
+            if let Some(member) = data.member.filter(|a| {
+                self.components
+                    .get(a.component as usize)
+                    .is_none_or(|b| a.node as usize >= b.nodes.len())
+            }) {
+                return Err(RulesetError::MemberOutOfRange { node, member });
+            }
+            if let Some(state) = data.states().find(|a| *a >= states) {
+                return Err(RulesetError::NodeOutOfRange { node, state });
+            }
+        }
+        for component in &self.components {
+            let len = component.nodes.len();
+            for (node, data) in component.nodes.iter().enumerate() {
+                if let Some(state) = data.states().find(|a| *a >= states) {
+                    return Err(RulesetError::ComponentOutOfRange {
+                        component: component.name.clone(),
+                        state,
+                    });
+                }
+                if let Some(edge) = data.edges.iter().find(|a| a.to >= len) {
+                    return Err(RulesetError::ComponentEdgeOutOfRange {
+                        component: component.name.clone(),
+                        node,
+                        to: edge.to,
+                    });
+                }
+            }
+            if let Some((port, data)) = component
+                .ports
+                .iter()
+                .enumerate()
+                .find(|(_, a)| a.node >= len)
+            {
+                return Err(RulesetError::PortOutOfRange {
+                    component: component.name.clone(),
+                    port,
+                    node: data.node,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> &Ruleset {
+        &self.rules
+    }
+
+    /// switches to `rules` if every node is in one of their states, otherwise keeps the old
+    /// ones. the next step looks at every node
+    pub fn set_rules(&mut self, rules: Ruleset) -> Result<(), RulesetError> {
+        let old = std::mem::replace(&mut self.rules, rules);
+        if let Err(err) = self.validate() {
+            self.rules = old;
+            return Err(err);
+        }
+        self.wake_all();
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+    /// advances every node by one tick. a synchronous step only looks at the nodes that can
+    /// change, which relies on edits going through `Graph`'s methods, otherwise call `wake`
     pub fn step(&mut self) {
-        for node in self.graph.nodes.iter_mut() {
+        if let Some(cycles) = &mut self.cycles {
+            cycles.before_step(&self.graph, self.tick);
+        }
+        if let Some(history) = &mut self.history {
+            history.check(&self.graph);
+        }
+        let mut active = self
+            .active
+            .take()
+            .filter(|a| self.update == UpdateMode::Synchronous && a.is_valid(&self.graph))
+            // the skipped nodes would fire the same rules again, which has to be counted
+            .filter(|_| self.stats.is_none());
+        let evaluated: Vec<usize> = match &mut active {
+            Some(active) => active.take(),
+            None => (0..self.graph.nodes.len()).collect(),
+        };
+        let registers = self
+            .rules
+            .rules
+            .iter()
+            .flatten()
+            .any(|a| a.changes_registers());
+        // for taking the step back and for the cycle hash
+        let before = (self.history.is_some() || self.cycles.is_some() && registers)
+            .then(|| Before::new(&self.graph, &evaluated, registers));
+        let evaluated = &evaluated[..];
+        for &node in evaluated {
+            let node = &mut self.graph.nodes[node];
             std::mem::swap(&mut node.read, &mut node.write);
         }
 
-        for node in 0..self.graph.nodes.len() {
-            self.rules.apply(node, &mut self.graph).unwrap();
+        let rng = Rng::new(self.seed, self.tick);
+        // a step that was taken back was counted the first time
+        let replay = self.history.as_ref().is_some_and(|a| a.future() > 0);
+        let mut stats = self.stats.as_mut().filter(|_| !replay);
+        if let Some(stats) = &mut stats {
+            if !stats.fits(&self.rules) {
+                **stats = RuleStats::new(&self.rules);
+            }
+            stats.steps += 1;
+        }
+        let mut fired = Fired {
+            topology: vec![],
+            stats,
+        };
+        if self.compiled.is_empty() {
+            self.compiled.push(CompiledRuleset::new(&self.rules));
+        }
+        let compiled = &mut self.compiled;
+        let graph = &mut self.graph;
+        if self.update == UpdateMode::Synchronous {
+            update_nodes(
+                compiled,
+                graph,
+                evaluated,
+                self.tick,
+                &rng,
+                self.threads,
+                &mut fired,
+            );
+        } else {
+            // later groups read the states written by earlier ones,
+            // afterwards `read` goes back to the state before the step
+            let previous: Vec<u32> = graph.nodes.iter().map(|a| a.read).collect();
+            for group in self.update.groups(graph.nodes.len(), &rng) {
+                update_nodes(
+                    compiled,
+                    graph,
+                    &group,
+                    self.tick,
+                    &rng,
+                    self.threads,
+                    &mut fired,
+                );
+                for &node in &group {
+                    graph.nodes[node].read = graph.nodes[node].write;
+                }
+            }
+            for (node, read) in graph.nodes.iter_mut().zip(previous) {
+                node.read = read;
+            }
+        }
+        // made before rewriting, so any topology change makes it invalid
+        let mut active = active.unwrap_or_else(|| ActiveSet::new(graph));
+        active.collect(graph, &compiled[0], evaluated);
+        self.active = Some(active);
+        if let Some(cycles) = &mut self.cycles {
+            let registers = before.as_ref().and_then(|a| a.registers());
+            for (i, &node) in evaluated.iter().enumerate() {
+                let data = &graph.nodes[node];
+                let old = registers.map_or(&data.registers, |a| &a[i]);
+                if data.read != data.write || *old != data.registers {
+                    cycles.changed(node, (data.read, old), (data.write, &data.registers));
+                }
+            }
+        }
+        let diff = before
+            .filter(|_| self.history.is_some())
+            .map(|a| Diff::new(self.tick, evaluated, &a, graph));
+        let mut topology = fired.topology;
+        let mut rewrites = vec![];
+        if !topology.is_empty() {
+            topology.sort();
+            rewrites = self.rewrite(topology);
+        }
+        if let (Some(history), Some(diff)) = (&mut self.history, diff) {
+            history.record(diff, rewrites, &self.graph);
+        }
+        self.tick += 1;
+        if let Some(cycles) = &mut self.cycles {
+            cycles.record(&self.graph, self.tick);
+        }
+    }
+
+    /// goes back to the tick before the last step, false if there is no history left
+    pub fn step_back(&mut self) -> bool {
+        self.history
+            .as_mut()
+            .and_then(|a| a.back(&mut self.graph))
+            .map(|tick| self.went_to(tick))
+            .is_some()
+    }
+
+    /// takes a step that was taken back again, false if there is none
+    pub fn step_forward(&mut self) -> bool {
+        let forward = self.history.as_ref().is_some_and(|a| a.future() > 0);
+        if forward {
+            self.step();
         }
+        forward
+    }
+
+    /// goes back or forth through the history as close to `tick` as it reaches
+    pub fn seek(&mut self, tick: u64) {
+        while self.tick > tick && self.step_back() {}
+        while self.tick < tick && self.step_forward() {}
+    }
+
+    /// starts or stops remembering steps for `step_back`
+    pub fn keep_history(&mut self, on: bool) {
+        self.history = on.then(History::default);
+    }
+
+    /// the oldest and newest tick `seek` can go to
+    pub fn history(&self) -> (u64, u64) {
+        self.history
+            .as_ref()
+            .map_or((self.tick, self.tick), |a| a.ticks(self.tick))
+    }
+
+    fn went_to(&mut self, tick: u64) {
+        self.tick = tick;
+        self.active = None;
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+    }
+
+    /// starts or stops looking for cycles
+    pub fn detect_cycles(&mut self, on: bool) {
+        self.cycles = on.then(CycleDetector::default);
+    }
+
+    /// the cycle the states are going around, if they came back to states from the last
+    /// `cycle::HISTORY` ticks. always `None` without `detect_cycles`
+    pub fn cycle(&self) -> Option<Cycle> {
+        self.cycles.as_ref().and_then(|a| a.cycle())
+    }
+
+    /// makes the next step look at `node` and its neighbours,
+    /// needed after changing its state, registers or clock divider by hand
+    pub fn wake(&mut self, node: usize) {
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+        if let Some(history) = &mut self.history {
+            history.forget_future();
+        }
+        if let Some(active) = &mut self.active {
+            if active.is_valid(&self.graph) {
+                active.wake(&self.graph, node);
+            } else {
+                // made before the graph was edited, the next step would throw it away anyway
+                self.active = None;
+            }
+        }
+    }
+
+    /// makes the next step look at every node and forgets the steps taken back,
+    /// call it after changing the seed or update mode
+    pub fn wake_all(&mut self) {
+        self.compiled.clear();
+        self.active = None;
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+        if let Some(history) = &mut self.history {
+            history.forget_future();
+        }
+    }
+
+    /// makes `nodes` a new component and their first instance, `None` if there are no nodes or
+    /// some are in an instance already
+    pub fn define_component(&mut self, name: String, nodes: &[usize]) -> Option<usize> {
+        if nodes.is_empty() || nodes.iter().any(|a| self.graph.nodes[*a].member.is_some()) {
+            return None;
+        }
+        let component = self.components.len();
+        let instance = self.next_instance();
+        self.components
+            .push(Component::from_nodes(name, &self.graph, nodes));
+        for (i, &node) in nodes.iter().enumerate() {
+            self.graph.nodes[node].member = Some(Member {
+                component: component as u32,
+                instance,
+                node: i as u32,
+            });
+        }
+        Some(component)
+    }
+
+    /// adds an instance of `component` around `position`, returns its nodes
+    pub fn instantiate(&mut self, component: usize, position: Vec2) -> Vec<usize> {
+        let instance = self.next_instance();
+        let start = self.graph.nodes.len();
+        let definition = &self.components[component];
+        for (i, node) in definition.nodes.iter().enumerate() {
+            let mut node = node.clone();
+            node.edges = vec![];
+            node.position += position;
+            node.member = Some(Member {
+                component: component as u32,
+                instance,
+                node: i as u32,
+            });
+            self.graph.add_node(node);
+        }
+        for (i, node) in definition.nodes.iter().enumerate() {
+            for edge in &node.edges {
+                self.graph
+                    .add_weighted_edge(start + i, start + edge.to, edge.weight);
+            }
+        }
+        (start..self.graph.nodes.len()).collect()
+    }
+
+    /// makes node `node` of `component` a port of `kind`, or no port for `None`.
+    /// false if there is no such node
+    pub fn set_port(&mut self, component: usize, node: usize, kind: Option<PortKind>) -> bool {
+        let Some(component) = self
+            .components
+            .get_mut(component)
+            .filter(|a| node < a.nodes.len())
+        else {
+            return false;
+        };
+        component.ports.retain(|a| a.node != node);
+        if let Some(kind) = kind {
+            component.ports.push(Port { node, kind });
+            component.ports.sort_by_key(|a| a.node);
+        }
+        true
+    }
+
+    /// makes `instance` as it is now, plus the nodes in `extra` that aren't in an instance, the
+    /// definition of its component and rewrites the other instances to match
+    pub fn redefine_component(&mut self, instance: u32, extra: &[usize]) {
+        let mut nodes = component::members(&self.graph, instance);
+        let Some(component) = nodes
+            .first()
+            .and_then(|a| self.graph.nodes[*a].member)
+            .map(|a| a.component)
+        else {
+            return;
+        };
+        // where each node of the old definition went in the new one
+        let mut moved = vec![None; self.components[component as usize].nodes.len()];
+        for (i, &node) in nodes.iter().enumerate() {
+            let old = self.graph.nodes[node].member.unwrap().node;
+            moved[old as usize] = Some(i as u32);
+        }
+        for &node in extra {
+            if self.graph.nodes[node].member.is_none() && !nodes.contains(&node) {
+                nodes.push(node);
+            }
+        }
+
+        let name = self.components[component as usize].name.clone();
+        let old = std::mem::replace(
+            &mut self.components[component as usize],
+            Component::from_nodes(name, &self.graph, &nodes),
+        );
+        for (i, &node) in nodes.iter().enumerate() {
+            self.graph.nodes[node].member = Some(Member {
+                component,
+                instance,
+                node: i as u32,
+            });
+        }
+        // ports marked by hand stay ports
+        for port in &old.ports {
+            if let Some(node) = moved.get(port.node).copied().flatten() {
+                if !self.components[component as usize].is_port(node as usize) {
+                    self.set_port(component as usize, node as usize, Some(port.kind));
+                }
+            }
+        }
+
+        let mut removed = vec![];
+        for (other, of) in component::instances(&self.graph) {
+            if other != instance && of == component {
+                removed.extend(self.rewrite_instance(other, &old, &moved));
+            }
+        }
+        removed.sort_unstable();
+        for node in removed.into_iter().rev() {
+            self.graph.remove_node(node);
+        }
+    }
+
+    /// brings `instance` from the `old` definition of its component to the current one,
+    /// returns the nodes that aren't in it anymore for the caller to remove
+    fn rewrite_instance(
+        &mut self,
+        instance: u32,
+        old: &Component,
+        moved: &[Option<u32>],
+    ) -> Vec<usize> {
+        let members = component::members(&self.graph, instance);
+        let component = self.graph.nodes[members[0]].member.unwrap().component;
+        let definition = &self.components[component as usize];
+        // the instance's offset from the definition, averaged in case nodes were dragged apart
+        let mut offset = Vec2::zero();
+        for &node in &members {
+            let member = self.graph.nodes[node].member.unwrap();
+            offset += self.graph.nodes[node].position - old.nodes[member.node as usize].position;
+        }
+        let offset = offset / members.len() as f32;
+
+        let mut nodes: Vec<Option<usize>> = vec![None; definition.nodes.len()];
+        let mut removed = vec![];
+        for &node in &members {
+            let member = self.graph.nodes[node].member.unwrap();
+            match moved.get(member.node as usize).copied().flatten() {
+                Some(new) => nodes[new as usize] = Some(node),
+                None => removed.push(node),
+            }
+        }
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let definition = &definition.nodes[i];
+            let member = Some(Member {
+                component,
+                instance,
+                node: i as u32,
+            });
+            match *node {
+                // the states are the instance's own, the settings come from the definition
+                Some(node) => {
+                    let node = &mut self.graph.nodes[node];
+                    node.position = definition.position + offset;
+                    node.note = definition.note.clone();
+                    node.period = definition.period;
+                    node.phase = definition.phase;
+                    node.pinned = definition.pinned;
+                    node.kind = definition.kind;
+                    node.member = member;
+                }
+                None => {
+                    let mut new = definition.clone();
+                    new.edges = vec![];
+                    new.position = definition.position + offset;
+                    new.member = member;
+                    self.graph.add_node(new);
+                    *node = Some(self.graph.nodes.len() - 1);
+                }
+            }
+        }
+
+        // the edges inside the instance are replaced, the ones to the outside stay
+        let nodes: Vec<usize> = nodes.into_iter().flatten().collect();
+        for &node in nodes.iter().chain(&removed) {
+            let inside: Vec<usize> = self.graph.nodes[node]
+                .edges
+                .iter()
+                .filter(|a| {
+                    self.graph.nodes[a.to]
+                        .member
+                        .is_some_and(|a| a.instance == instance)
+                })
+                .map(|a| a.to)
+                .collect();
+            for to in inside {
+                self.graph.remove_edge(node, to);
+            }
+        }
+        for (i, &node) in nodes.iter().enumerate() {
+            for edge in &self.components[component as usize].nodes[i].edges {
+                self.graph
+                    .add_weighted_edge(node, nodes[edge.to], edge.weight);
+            }
+        }
+        removed
+    }
+
+    /// an instance id no node has yet
+    fn next_instance(&self) -> u32 {
+        self.graph
+            .nodes
+            .iter()
+            .filter_map(|a| a.member.map(|a| a.instance + 1))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// runs the topology actions of the rules that fired this tick in node order,
+    /// looking at the states from before the tick.
+    /// deletions go last and from the highest index down so the pending indices stay valid.
+    /// returns the changes for the history
+    fn rewrite(&mut self, fired: Vec<(usize, u32, usize)>) -> Vec<Rewrite> {
+        let mut rewrites = vec![];
+        let connect = |graph: &mut Graph, rewrites: &mut Vec<Rewrite>, from: usize, to: usize| {
+            if graph.add_edge(from, to) {
+                rewrites.push(Rewrite::Connected {
+                    from: from as u32,
+                    to: to as u32,
+                });
+            }
+        };
+        let mut deleted = vec![];
+        for (node, state, rule) in fired {
+            for action in &self.rules.rules[state as usize][rule].actions {
+                match *action {
+                    Action::Spawn { state } => {
+                        let parent = &self.graph.nodes[node];
+                        // golden angle so siblings spread out
+                        let angle = parent.edges.len() as f32 * 2.399963;
+                        let position = parent.position + Vec2::new(angle.cos(), angle.sin()) * 80.0;
+                        self.graph
+                            .add_node(Node::new(state, state, vec![], position));
+                        rewrites.push(Rewrite::Added);
+                        let child = self.graph.nodes.len() - 1;
+                        connect(&mut self.graph, &mut rewrites, node, child);
+                        connect(&mut self.graph, &mut rewrites, child, node);
+                    }
+                    Action::Delete => deleted.push(node),
+                    Action::Connect { state } => {
+                        let graph = &self.graph;
+                        let targets: Vec<usize> =
+                            graph.layers(node, Direction::Outgoing, 2, |layers| {
+                                layers
+                                    .get(1)
+                                    .into_iter()
+                                    .flatten()
+                                    .copied()
+                                    .filter(|a| graph.nodes[*a].read == state)
+                                    .collect()
+                            });
+                        for target in targets {
+                            connect(&mut self.graph, &mut rewrites, node, target);
+                        }
+                    }
+                    Action::Disconnect { state } => {
+                        let targets: Vec<Edge> = self.graph.nodes[node]
+                            .edges
+                            .iter()
+                            .filter(|a| self.graph.nodes[a.to].read == state)
+                            .copied()
+                            .collect();
+                        for target in targets {
+                            self.graph.remove_edge(node, target.to);
+                            rewrites.push(Rewrite::Disconnected {
+                                from: node as u32,
+                                to: target.to as u32,
+                                weight: target.weight,
+                            });
+                        }
+                    }
+                    Action::Add { .. } | Action::Set { .. } => (),
+                }
+            }
+        }
+        deleted.dedup();
+        for node in deleted.into_iter().rev() {
+            let id = self.graph.id(node);
+            let removed = self.graph.remove_node(node);
+            rewrites.push(Rewrite::Removed {
+                index: node as u32,
+                id,
+                node: removed,
+            });
+        }
+        rewrites
     }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Rule {
-    pattern: Pattern,
-    replacement: u32,
+    pub pattern: Pattern,
+    pub replacement: u32,
+    /// chance that the rule fires when its pattern matches, otherwise the next rule is tried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probability: Option<f64>,
+    /// register and topology changes done when the rule fires
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<Action>,
+}
+
+/// what a rule does besides setting the state.
+/// register actions happen right away, topology actions after every node got its new state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Action {
+    /// adds `amount` to the register, negative amounts count down
+    Add {
+        register: usize,
+        amount: i32,
+    },
+    Set {
+        register: usize,
+        value: i32,
+    },
+    /// adds a node in `state` connected both ways to this one
+    Spawn {
+        state: u32,
+    },
+    /// removes this node
+    Delete,
+    /// adds edges to the nodes in `state` two edges away
+    Connect {
+        state: u32,
+    },
+    /// removes the edges to neighbours in `state`
+    Disconnect {
+        state: u32,
+    },
+}
+
+impl Action {
+    pub fn changes_topology(&self) -> bool {
+        !matches!(self, Action::Add { .. } | Action::Set { .. })
+    }
+
+    /// the state the action refers to, if any
+    pub fn state(&self) -> Option<u32> {
+        match *self {
+            Action::Spawn { state } | Action::Connect { state } | Action::Disconnect { state } => {
+                Some(state)
+            }
+            _ => None,
+        }
+    }
+
+    fn run(&self, registers: &mut [i32]) {
+        match *self {
+            Action::Add { register, amount } => {
+                if let Some(a) = registers.get_mut(register) {
+                    *a = a.wrapping_add(amount)
+                }
+            }
+            Action::Set { register, value } => {
+                if let Some(a) = registers.get_mut(register) {
+                    *a = value
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Compare {
+    Equal,
+    Gth,
+    Lth,
+    Geq,
+    Leq,
+}
+
+impl Compare {
+    pub fn compare(&self, a: i32, b: i32) -> bool {
+        match self {
+            Compare::Equal => a == b,
+            Compare::Gth => a > b,
+            Compare::Lth => a < b,
+            Compare::Geq => a >= b,
+            Compare::Leq => a <= b,
+        }
+    }
 }
 
 impl Rule {
@@ -35,97 +874,422 @@ impl Rule {
         Self {
             pattern,
             replacement,
+            probability: None,
+            actions: vec![],
+        }
+    }
+
+    pub fn with_action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    pub fn with_probability(mut self, probability: f64) -> Self {
+        self.probability = Some(probability);
+        self
+    }
+
+    /// whether the rule has actions that change registers
+    pub fn changes_registers(&self) -> bool {
+        self.actions.iter().any(|a| !a.changes_topology())
+    }
+
+    /// a rule that always fires, every state needs one
+    pub fn is_fallback(&self) -> bool {
+        self.pattern == Pattern::Wildcard && self.probability.is_none()
+    }
+
+    /// writes the new state and runs the register actions
+    pub(crate) fn fire(&self, node: &mut Node) {
+        node.write = self.replacement;
+        for action in &self.actions {
+            action.run(&mut node.registers);
         }
     }
 }
 
+/// counting patterns compare the summed edge weights of the neighbours in `state` to `number`.
+/// neighbours are the outgoing edges unless a `Directed` pattern above says otherwise
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
 pub enum Pattern {
-    Equal { state: u32, number: u32 },
-    Gth { state: u32, number: u32 },
-    Lth { state: u32, number: u32 },
-    Geq { state: u32, number: u32 },
-    Leq { state: u32, number: u32 },
+    Equal {
+        state: u32,
+        number: i32,
+    },
+    Gth {
+        state: u32,
+        number: i32,
+    },
+    Lth {
+        state: u32,
+        number: i32,
+    },
+    Geq {
+        state: u32,
+        number: i32,
+    },
+    Leq {
+        state: u32,
+        number: i32,
+    },
+    /// more neighbours in `state` than in `other`
+    MoreThan {
+        state: u32,
+        other: u32,
+    },
+    /// at least `percent` of the total neighbour weight is in `state`
+    AtLeastPercent {
+        state: u32,
+        percent: u32,
+    },
+    /// the neighbours add up to at least `number`, whatever their state
+    DegreeGeq {
+        number: i32,
+    },
+    /// compares one of the node's own registers to `number`
+    Register {
+        register: usize,
+        compare: Compare,
+        number: i32,
+    },
     Or(Box<Pattern>, Box<Pattern>),
     And(Box<Pattern>, Box<Pattern>),
     Not(Box<Pattern>),
+    /// counts the patterns inside over the neighbours in `Direction`
+    Directed(Direction, Box<Pattern>),
+    /// counts the patterns inside over every node at most this many edges away
+    Within(u32, Box<Pattern>),
+    /// counts the patterns inside over the nodes exactly this many edges away
+    AtDistance(u32, Box<Pattern>),
     Wildcard,
 }
 
 impl Pattern {
+    /// adds the states the pattern counts to `states`
+    pub fn states(&self, states: &mut Vec<u32>) {
+        match self {
+            Pattern::Equal { state, .. }
+            | Pattern::Gth { state, .. }
+            | Pattern::Lth { state, .. }
+            | Pattern::Geq { state, .. }
+            | Pattern::Leq { state, .. }
+            | Pattern::AtLeastPercent { state, .. } => states.push(*state),
+            Pattern::MoreThan { state, other } => states.extend([*state, *other]),
+            Pattern::Or(a, b) | Pattern::And(a, b) => {
+                a.states(states);
+                b.states(states);
+            }
+            Pattern::Not(a)
+            | Pattern::Directed(_, a)
+            | Pattern::Within(_, a)
+            | Pattern::AtDistance(_, a) => a.states(states),
+            Pattern::DegreeGeq { .. } | Pattern::Register { .. } | Pattern::Wildcard => (),
+        }
+    }
+
     pub fn pattern_match(&self, node: usize, graph: &Graph) -> bool {
+        self.matches(
+            node,
+            graph,
+            Neighbourhood {
+                direction: Direction::Outgoing,
+                distance: Distance::Adjacent,
+            },
+        )
+    }
+
+    fn matches(&self, node: usize, graph: &Graph, around: Neighbourhood) -> bool {
+        let count = |state: &u32| around.count(Some(*state), node, graph);
         match self {
-            Pattern::Equal { state, number } => {
-                graph.nodes[node]
-                    .edges
-                    .iter()
-                    .filter(|a| graph.nodes[**a].read == *state)
-                    .count() as u32
-                    == *number
-            }
-            Pattern::Gth { state, number } => {
-                graph.nodes[node]
-                    .edges
-                    .iter()
-                    .filter(|a| graph.nodes[**a].read == *state)
-                    .count() as u32
-                    > *number
-            }
-            Pattern::Lth { state, number } => {
-                (graph.nodes[node]
-                    .edges
-                    .iter()
-                    .filter(|a| graph.nodes[**a].read == *state)
-                    .count() as u32)
-                    < *number
-            }
-            Pattern::Geq { state, number } => {
-                (graph.nodes[node]
-                    .edges
-                    .iter()
-                    .filter(|a| graph.nodes[**a].read == *state)
-                    .count() as u32)
-                    >= *number
-            }
-            Pattern::Leq { state, number } => {
-                (graph.nodes[node]
-                    .edges
-                    .iter()
-                    .filter(|a| graph.nodes[**a].read == *state)
-                    .count() as u32)
-                    <= *number
-            }
-            Pattern::Or(a, b) => a.pattern_match(node, graph) || b.pattern_match(node, graph),
-            Pattern::And(a, b) => a.pattern_match(node, graph) && b.pattern_match(node, graph),
-            Pattern::Not(a) => !a.pattern_match(node, graph),
+            Pattern::Equal { state, number } => count(state) == *number,
+            Pattern::Gth { state, number } => count(state) > *number,
+            Pattern::Lth { state, number } => count(state) < *number,
+            Pattern::Geq { state, number } => count(state) >= *number,
+            Pattern::Leq { state, number } => count(state) <= *number,
+            Pattern::MoreThan { state, other } => count(state) > count(other),
+            Pattern::AtLeastPercent { state, percent } => {
+                let total = around.count(None, node, graph);
+                total > 0 && count(state) as i64 * 100 >= *percent as i64 * total as i64
+            }
+            Pattern::DegreeGeq { number } => around.count(None, node, graph) >= *number,
+            Pattern::Register {
+                register,
+                compare,
+                number,
+            } => graph.nodes[node]
+                .registers
+                .get(*register)
+                .is_some_and(|a| compare.compare(*a, *number)),
+            Pattern::Or(a, b) => a.matches(node, graph, around) || b.matches(node, graph, around),
+            Pattern::And(a, b) => a.matches(node, graph, around) && b.matches(node, graph, around),
+            Pattern::Not(a) => !a.matches(node, graph, around),
+            Pattern::Directed(direction, a) => a.matches(
+                node,
+                graph,
+                Neighbourhood {
+                    direction: *direction,
+                    ..around
+                },
+            ),
+            Pattern::Within(k, a) => a.matches(
+                node,
+                graph,
+                Neighbourhood {
+                    distance: Distance::Within(*k),
+                    ..around
+                },
+            ),
+            Pattern::AtDistance(k, a) => a.matches(
+                node,
+                graph,
+                Neighbourhood {
+                    distance: Distance::Exactly(*k),
+                    ..around
+                },
+            ),
             Pattern::Wildcard => true,
         }
     }
 }
 
+/// the nodes counting patterns look at, set by the modifier patterns above them
+#[derive(Clone, Copy)]
+struct Neighbourhood {
+    direction: Direction,
+    distance: Distance,
+}
+
+#[derive(Clone, Copy)]
+enum Distance {
+    Adjacent,
+    Within(u32),
+    Exactly(u32),
+}
+
+impl Neighbourhood {
+    /// sums the neighbours in `state`, or all of them for `None`.
+    /// direct neighbours count with their edge weight, further ones count once
+    fn count(&self, state: Option<u32>, node: usize, graph: &Graph) -> i32 {
+        let in_state = |a: usize| state.is_none_or(|state| graph.nodes[a].read == state);
+        match self.distance {
+            Distance::Adjacent => graph
+                .neighbours(node, self.direction)
+                .filter(|a| in_state(a.to))
+                .map(|a| a.weight)
+                .sum(),
+            Distance::Within(k) => graph.layers(node, self.direction, k, |layers| {
+                layers.iter().flatten().filter(|a| in_state(**a)).count() as i32
+            }),
+            Distance::Exactly(k) => graph.layers(node, self.direction, k, |layers| {
+                match k.checked_sub(1).and_then(|a| layers.get(a as usize)) {
+                    Some(layer) => layer.iter().filter(|a| in_state(**a)).count() as i32,
+                    None => 0,
+                }
+            }),
+        }
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Ruleset {
     pub names: Vec<String>,
     pub rules: Vec<Vec<Rule>>,
 }
 
-impl Ruleset {
-    pub fn new(rules: Vec<Vec<Rule>>, names: Vec<String>) -> Option<Self> {
-        for state in &rules {
-            if !state.iter().any(|a| a.pattern == Pattern::Wildcard) {
-                return None;
+/// why a ruleset or a world can't be stepped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RulesetError {
+    /// every state needs a name
+    NamesMismatch { names: usize, states: usize },
+    /// the state has no rule that always fires
+    MissingFallback { state: usize },
+    /// the rule switches to a state that doesn't exist
+    ReplacementOutOfRange {
+        state: usize,
+        rule: usize,
+        replacement: u32,
+    },
+    /// the rule's pattern or actions mention a state that doesn't exist
+    UnknownState {
+        state: usize,
+        rule: usize,
+        unknown: u32,
+    },
+    /// the rule comes after one that always fires, so it never does
+    UnreachableRule { state: usize, rule: usize },
+    /// a node is in or pulses a state that doesn't exist
+    NodeOutOfRange { node: usize, state: u32 },
+    /// a node of a component is in a state that doesn't exist
+    ComponentOutOfRange { component: String, state: u32 },
+    /// a node of a component has an edge to a node the component doesn't have
+    ComponentEdgeOutOfRange {
+        component: String,
+        node: usize,
+        to: usize,
+    },
+    /// a port of a component is a node the component doesn't have
+    PortOutOfRange {
+        component: String,
+        port: usize,
+        node: usize,
+    },
+    /// a node has an edge to a node that doesn't exist
+    EdgeOutOfRange { node: usize, to: usize },
+    /// a node is in a component that doesn't exist or is a node the component doesn't have
+    MemberOutOfRange { node: usize, member: Member },
+    /// a saved graph has a different number of node ids than nodes
+    IdsMismatch { ids: usize, nodes: usize },
+    /// two nodes of a saved graph have ids with the same slot
+    DuplicateSlot { slot: u32 },
+}
+
+impl fmt::Display for RulesetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesetError::NamesMismatch { names, states } => {
+                write!(f, "{} names for {} states", names, states)
+            }
+            RulesetError::MissingFallback { state } => {
+                write!(f, "state {} needs a `* -> state` fallback rule", state)
+            }
+            RulesetError::ReplacementOutOfRange {
+                state,
+                rule,
+                replacement,
+            } => write!(
+                f,
+                "rule {} of state {} switches to state {}, which doesn't exist",
+                rule, state, replacement
+            ),
+            RulesetError::UnknownState {
+                state,
+                rule,
+                unknown,
+            } => write!(
+                f,
+                "rule {} of state {} mentions state {}, which doesn't exist",
+                rule, state, unknown
+            ),
+            RulesetError::UnreachableRule { state, rule } => write!(
+                f,
+                "rule {} of state {} never fires, a rule before it always does",
+                rule, state
+            ),
+            RulesetError::NodeOutOfRange { node, state } => {
+                write!(
+                    f,
+                    "node {} is in state {}, which doesn't exist",
+                    node, state
+                )
+            }
+            RulesetError::ComponentOutOfRange { component, state } => write!(
+                f,
+                "component `{}` has a node in state {}, which doesn't exist",
+                component, state
+            ),
+            RulesetError::ComponentEdgeOutOfRange {
+                component,
+                node,
+                to,
+            } => write!(
+                f,
+                "node {} of component `{}` has an edge to node {}, which doesn't exist",
+                node, component, to
+            ),
+            RulesetError::PortOutOfRange {
+                component,
+                port,
+                node,
+            } => write!(
+                f,
+                "port {} of component `{}` is node {}, which doesn't exist",
+                port, component, node
+            ),
+            RulesetError::EdgeOutOfRange { node, to } => write!(
+                f,
+                "node {} has an edge to node {}, which doesn't exist",
+                node, to
+            ),
+            RulesetError::MemberOutOfRange { node, member } => write!(
+                f,
+                "node {} is node {} of component {}, which doesn't exist",
+                node, member.node, member.component
+            ),
+            RulesetError::IdsMismatch { ids, nodes } => {
+                write!(f, "{} node ids for {} nodes", ids, nodes)
+            }
+            RulesetError::DuplicateSlot { slot } => {
+                write!(f, "more than one node has an id in slot {}", slot)
             }
         }
-        Some(Ruleset { rules, names })
+    }
+}
+
+impl std::error::Error for RulesetError {}
+
+impl Ruleset {
+    pub fn new(rules: Vec<Vec<Rule>>, names: Vec<String>) -> Result<Self, RulesetError> {
+        let ruleset = Ruleset { rules, names };
+        ruleset.validate()?;
+        Ok(ruleset)
     }
 
-    pub fn apply(&self, idx: usize, graph: &mut Graph) -> Option<()> {
-        for rule in &self.rules[graph.nodes[idx].read as usize] {
-            if rule.pattern.pattern_match(idx, graph) {
-                graph.nodes[idx].write = rule.replacement;
-                return Some(());
+    /// checks what `new` checks, for rulesets that were built by hand or loaded
+    pub fn validate(&self) -> Result<(), RulesetError> {
+        let states = self.rules.len();
+        if self.names.len() != states {
+            return Err(RulesetError::NamesMismatch {
+                names: self.names.len(),
+                states,
+            });
+        }
+        for (state, rules) in self.rules.iter().enumerate() {
+            let mut fallback = false;
+            for (i, rule) in rules.iter().enumerate() {
+                if fallback {
+                    return Err(RulesetError::UnreachableRule { state, rule: i });
+                }
+                fallback = rule.is_fallback();
+                if rule.replacement as usize >= states {
+                    return Err(RulesetError::ReplacementOutOfRange {
+                        state,
+                        rule: i,
+                        replacement: rule.replacement,
+                    });
+                }
+                let mut mentioned = vec![];
+                rule.pattern.states(&mut mentioned);
+                mentioned.extend(rule.actions.iter().filter_map(|a| a.state()));
+                if let Some(unknown) = mentioned.into_iter().find(|a| *a as usize >= states) {
+                    return Err(RulesetError::UnknownState {
+                        state,
+                        rule: i,
+                        unknown,
+                    });
+                }
+            }
+            if !fallback {
+                return Err(RulesetError::MissingFallback { state });
             }
         }
-        None
+        Ok(())
+    }
+
+    /// sets the next state of the node and returns the index of the rule that fired
+    pub fn apply(&self, idx: usize, graph: &mut Graph, rng: &Rng) -> Option<usize> {
+        let rule = self.matching_rule(idx, graph, rng)?;
+        self.rules[graph.nodes[idx].read as usize][rule].fire(&mut graph.nodes[idx]);
+        Some(rule)
+    }
+
+    /// the index of the rule that fires for the node, without firing it
+    pub fn matching_rule(&self, idx: usize, graph: &Graph, rng: &Rng) -> Option<usize> {
+        self.rules[graph.nodes[idx].read as usize]
+            .iter()
+            .enumerate()
+            .position(|(i, rule)| {
+                rule.pattern.pattern_match(idx, graph)
+                    && rule.probability.is_none_or(|p| rng.roll(idx, i) < p)
+            })
     }
 }
@@ -0,0 +1,257 @@
+//! A faster way to apply a `Ruleset`.
+//!
+//! Most patterns only look at how many direct neighbours are in each state. For those the
+//! neighbourhood is summed into a histogram once per node and every comparison reads from it,
+//! instead of walking the edges again for each one. For states where every rule is like that
+//! the matching rules are looked up by histogram, so nodes with a neighbourhood seen before
+//! skip matching entirely. Patterns that look further (directions, distances, registers) fall
+//! back to `Pattern::pattern_match`.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+use crate::automaton::{Pattern, Ruleset};
+use crate::graph::{Direction, Graph};
+use crate::rng::Rng;
+
+/// how many histograms a state remembers before it stops adding to its table
+const TABLE_LIMIT: usize = 1 << 16;
+
+/// built once per ruleset, the tables fill up as nodes are matched
+#[derive(Clone, Debug)]
+pub struct CompiledRuleset {
+    ruleset: Ruleset,
+    states: Vec<CompiledState>,
+    /// summed weights of the neighbours per state, the total of all of them last
+    histogram: Vec<i32>,
+}
+
+#[derive(Clone, Debug)]
+struct CompiledState {
+    /// whether each rule's pattern is decided from the histogram. all false if the state's
+    /// patterns only walk the edges once or twice, pattern matching is faster then
+    local: Vec<bool>,
+    /// whether any of them is, otherwise the histogram isn't needed
+    any_local: bool,
+    /// the rules matching each histogram seen so far, only if all rules are local
+    table: Option<HashMap<Vec<i32>, Vec<usize>, BuildHasherDefault<HistogramHasher>>>,
+    /// whether each rule has actions that change the topology
+    topology: Vec<bool>,
+    /// whether a node in this state can change without its neighbourhood changing
+    restless: bool,
+}
+
+impl CompiledRuleset {
+    pub fn new(ruleset: &Ruleset) -> Self {
+        let states_len = ruleset.rules.len() as u32;
+        let states = ruleset
+            .rules
+            .iter()
+            .map(|rules| {
+                let local: Vec<bool> = rules
+                    .iter()
+                    .map(|a| is_local(&a.pattern, states_len))
+                    .collect();
+                // one or two walks over the edges don't pay for building the histogram
+                let walks: usize = rules
+                    .iter()
+                    .zip(&local)
+                    .filter(|(_, local)| **local)
+                    .map(|(a, _)| edge_walks(&a.pattern))
+                    .sum();
+                let local = if walks > 2 {
+                    local
+                } else {
+                    vec![false; rules.len()]
+                };
+                let any_local = local.iter().any(|a| *a);
+                let table = local.iter().all(|a| *a).then(HashMap::default);
+                let topology = rules
+                    .iter()
+                    .map(|a| a.actions.iter().any(|a| a.changes_topology()))
+                    .collect();
+                let restless = rules.iter().any(|a| {
+                    a.probability.is_some() || !a.actions.is_empty() || !is_adjacent(&a.pattern)
+                });
+                CompiledState {
+                    local,
+                    any_local,
+                    table,
+                    topology,
+                    restless,
+                }
+            })
+            .collect();
+        Self {
+            ruleset: ruleset.clone(),
+            states,
+            histogram: vec![0; ruleset.rules.len() + 1],
+        }
+    }
+
+    pub fn ruleset(&self) -> &Ruleset {
+        &self.ruleset
+    }
+
+    /// whether `rule` of `state` has actions that change the topology
+    pub fn changes_topology(&self, state: u32, rule: usize) -> bool {
+        self.states[state as usize].topology[rule]
+    }
+
+    /// whether nodes in `state` have to be looked at every tick, because their rules are
+    /// random, have actions or look past their neighbours
+    pub fn is_restless(&self, state: u32) -> bool {
+        self.states[state as usize].restless
+    }
+
+    /// same as `Ruleset::apply`
+    pub fn apply(&mut self, idx: usize, graph: &mut Graph, rng: &Rng) -> Option<usize> {
+        let rule = self.matching_rule(idx, graph, rng)?;
+        self.ruleset.rules[graph.nodes[idx].read as usize][rule].fire(&mut graph.nodes[idx]);
+        Some(rule)
+    }
+
+    /// same as `Ruleset::matching_rule`
+    pub fn matching_rule(&mut self, idx: usize, graph: &Graph, rng: &Rng) -> Option<usize> {
+        let state = graph.nodes[idx].read as usize;
+        let rules = &self.ruleset.rules[state];
+        let compiled = &mut self.states[state];
+
+        if !compiled.any_local {
+            return self.ruleset.matching_rule(idx, graph, rng);
+        }
+
+        let states = self.histogram.len() - 1;
+        let (counts, total) = self.histogram.split_at_mut(states);
+        counts.fill(0);
+        total[0] = 0;
+        for edge in &graph.nodes[idx].edges {
+            if let Some(count) = counts.get_mut(graph.nodes[edge.to].read as usize) {
+                *count += edge.weight;
+            }
+            total[0] += edge.weight;
+        }
+
+        let rolls = |i: &usize| rules[*i].probability.is_none_or(|p| rng.roll(idx, *i) < p);
+        if let Some(table) = &mut compiled.table {
+            match table.get(&self.histogram) {
+                Some(matching) => matching.iter().copied().find(rolls),
+                None => {
+                    let matching: Vec<usize> = (0..rules.len())
+                        .filter(|i| matches_histogram(&rules[*i].pattern, &self.histogram))
+                        .collect();
+                    let fired = matching.iter().copied().find(rolls);
+                    if table.len() < TABLE_LIMIT {
+                        table.insert(self.histogram.clone(), matching);
+                    }
+                    fired
+                }
+            }
+        } else {
+            (0..rules.len()).find(|i| {
+                let matched = if compiled.local[*i] {
+                    matches_histogram(&rules[*i].pattern, &self.histogram)
+                } else {
+                    rules[*i].pattern.pattern_match(idx, graph)
+                };
+                matched && rolls(i)
+            })
+        }
+    }
+}
+
+/// whether the pattern only depends on the weighted counts of outgoing neighbours
+/// in states the ruleset knows about
+fn is_local(pattern: &Pattern, states_len: u32) -> bool {
+    match pattern {
+        Pattern::Equal { state, .. }
+        | Pattern::Gth { state, .. }
+        | Pattern::Lth { state, .. }
+        | Pattern::Geq { state, .. }
+        | Pattern::Leq { state, .. }
+        | Pattern::AtLeastPercent { state, .. } => *state < states_len,
+        Pattern::MoreThan { state, other } => *state < states_len && *other < states_len,
+        Pattern::DegreeGeq { .. } | Pattern::Wildcard => true,
+        Pattern::Or(a, b) | Pattern::And(a, b) => {
+            is_local(a, states_len) && is_local(b, states_len)
+        }
+        Pattern::Not(a) | Pattern::Directed(Direction::Outgoing, a) => is_local(a, states_len),
+        Pattern::Directed(..)
+        | Pattern::Within(..)
+        | Pattern::AtDistance(..)
+        | Pattern::Register { .. } => false,
+    }
+}
+
+/// whether the pattern only looks at the node and its neighbours in either direction
+fn is_adjacent(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Or(a, b) | Pattern::And(a, b) => is_adjacent(a) && is_adjacent(b),
+        Pattern::Not(a) | Pattern::Directed(_, a) => is_adjacent(a),
+        Pattern::Within(..) | Pattern::AtDistance(..) => false,
+        _ => true,
+    }
+}
+
+/// how many times matching a local pattern walks over the edges at most
+fn edge_walks(pattern: &Pattern) -> usize {
+    match pattern {
+        Pattern::Wildcard => 0,
+        Pattern::MoreThan { .. } => 2,
+        Pattern::Or(a, b) | Pattern::And(a, b) => edge_walks(a) + edge_walks(b),
+        Pattern::Not(a) | Pattern::Directed(_, a) => edge_walks(a),
+        _ => 1,
+    }
+}
+
+/// `Pattern::pattern_match` for local patterns
+fn matches_histogram(pattern: &Pattern, histogram: &[i32]) -> bool {
+    let count = |state: &u32| histogram[*state as usize];
+    let total = histogram[histogram.len() - 1];
+    match pattern {
+        Pattern::Equal { state, number } => count(state) == *number,
+        Pattern::Gth { state, number } => count(state) > *number,
+        Pattern::Lth { state, number } => count(state) < *number,
+        Pattern::Geq { state, number } => count(state) >= *number,
+        Pattern::Leq { state, number } => count(state) <= *number,
+        Pattern::MoreThan { state, other } => count(state) > count(other),
+        Pattern::AtLeastPercent { state, percent } => {
+            total > 0 && count(state) as i64 * 100 >= *percent as i64 * total as i64
+        }
+        Pattern::DegreeGeq { number } => total >= *number,
+        Pattern::Or(a, b) => matches_histogram(a, histogram) || matches_histogram(b, histogram),
+        Pattern::And(a, b) => matches_histogram(a, histogram) && matches_histogram(b, histogram),
+        Pattern::Not(a) => !matches_histogram(a, histogram),
+        Pattern::Directed(_, a) => matches_histogram(a, histogram),
+        Pattern::Wildcard => true,
+        Pattern::Within(..) | Pattern::AtDistance(..) | Pattern::Register { .. } => {
+            unreachable!("not a local pattern")
+        }
+    }
+}
+
+/// histograms are short and hashed once per node, the default hasher costs more than matching
+#[derive(Clone, Debug, Default)]
+struct HistogramHasher(u64);
+
+impl Hasher for HistogramHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u32(u32::from_le_bytes(word));
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0 = (self.0.rotate_left(5) ^ i as u64).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u32(i as u32);
+    }
+}
@@ -0,0 +1,143 @@
+//! Reusable subgraphs.
+//!
+//! A component is a named subgraph, its ports are the nodes that connect to the rest of the graph.
+//! Instances are kept expanded in `Graph`: each node of an instance is an ordinary node with a
+//! `Member` saying which instance it belongs to and which node of the component it is, so
+//! stepping, the history and the active set see nothing special. The editor draws an instance
+//! collapsed into one node with its ports around it.
+//!
+//! Changing a component rewrites every instance of it. Nodes that are still in the component
+//! keep their states, registers and the edges to the outside, only their edges inside the
+//! instance and their settings follow the new definition.
+
+use crate::graph::{Edge, Graph, Node};
+use crate::vec2::Vec2;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Component {
+    pub name: String,
+    /// positions are relative to the middle of the component, edges only go between its nodes
+    pub nodes: Vec<Node>,
+    pub ports: Vec<Port>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Port {
+    /// index into `Component::nodes`
+    pub node: usize,
+    pub kind: PortKind,
+}
+
+/// how a port connects to the outside. a node counts the nodes it has edges to, so an input
+/// port has edges going out of the instance and an output port has edges coming in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PortKind {
+    Input,
+    Output,
+    Both,
+}
+
+/// marks a node as part of an instance of a component
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Member {
+    /// index into `Automaton::components`
+    pub component: u32,
+    pub instance: u32,
+    /// index into `Component::nodes`
+    pub node: u32,
+}
+
+impl Component {
+    /// the component made of `nodes` of `graph`, with the nodes connected to other nodes as
+    /// ports. more can be marked with `Automaton::set_port`
+    pub fn from_nodes(name: String, graph: &Graph, nodes: &[usize]) -> Self {
+        // where each node ends up in the component
+        let mut inside = vec![None; graph.nodes.len()];
+        for (i, &node) in nodes.iter().enumerate() {
+            inside[node] = Some(i);
+        }
+        let middle = middle(graph, nodes);
+
+        let mut ports = vec![];
+        for (i, &node) in nodes.iter().enumerate() {
+            let input = graph.nodes[node]
+                .edges
+                .iter()
+                .any(|a| inside[a.to].is_none());
+            let output = graph.nodes[node]
+                .incoming
+                .iter()
+                .any(|a| inside[a.to].is_none());
+            let kind = match (input, output) {
+                (true, true) => PortKind::Both,
+                (true, false) => PortKind::Input,
+                (false, true) => PortKind::Output,
+                (false, false) => continue,
+            };
+            ports.push(Port { node: i, kind });
+        }
+
+        let nodes = nodes
+            .iter()
+            .map(|&node| {
+                let mut node = graph.nodes[node].clone();
+                node.edges = node
+                    .edges
+                    .iter()
+                    .filter_map(|a| inside[a.to].map(|to| Edge::new(to, a.weight)))
+                    .collect();
+                node.incoming = vec![];
+                node.position -= middle;
+                node.member = None;
+                node
+            })
+            .collect();
+        Self { name, nodes, ports }
+    }
+
+    pub fn is_port(&self, node: usize) -> bool {
+        self.port(node).is_some()
+    }
+
+    /// how `node` connects to the outside, `None` if it isn't a port
+    pub fn port(&self, node: usize) -> Option<PortKind> {
+        self.ports.iter().find(|a| a.node == node).map(|a| a.kind)
+    }
+}
+
+/// the average position of `nodes`
+pub fn middle(graph: &Graph, nodes: &[usize]) -> Vec2 {
+    let mut sum = Vec2::zero();
+    for &node in nodes {
+        sum += graph.nodes[node].position;
+    }
+    sum / nodes.len().max(1) as f32
+}
+
+/// the nodes of `instance` in the order of the component's nodes
+pub fn members(graph: &Graph, instance: u32) -> Vec<usize> {
+    let mut members: Vec<(u32, usize)> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, a)| {
+            a.member
+                .filter(|a| a.instance == instance)
+                .map(|a| (a.node, i))
+        })
+        .collect();
+    members.sort();
+    members.into_iter().map(|a| a.1).collect()
+}
+
+/// every `(instance, component)` in the graph
+pub fn instances(graph: &Graph) -> Vec<(u32, u32)> {
+    let mut instances: Vec<(u32, u32)> = graph
+        .nodes
+        .iter()
+        .filter_map(|a| a.member.map(|a| (a.instance, a.component)))
+        .collect();
+    instances.sort();
+    instances.dedup();
+    instances
+}
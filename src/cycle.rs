@@ -0,0 +1,156 @@
+//! Notices when the automaton comes back to states it was in before.
+//!
+//! The states and registers of all nodes are hashed into one number that is kept up to date
+//! from the nodes that changed, and the hashes of the last `HISTORY` ticks are remembered
+//! together with where every clock divider and pulse generator is in its period. Seeing a hash
+//! again means the automaton went around a cycle, or very rarely that two different states
+//! collided. With random rules the same states don't have to play out the same way twice, there
+//! a cycle only says that the states repeated.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+
+use crate::graph::{Graph, Node, NodeKind, REGISTERS};
+use crate::rng::splitmix64;
+
+/// how many ticks back cycles are looked for
+pub const HISTORY: usize = 4096;
+
+/// the states at `start + period` were the same as at `start`, and have kept repeating since
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    pub period: u64,
+    pub start: u64,
+}
+
+impl Cycle {
+    /// whether nothing changes anymore
+    pub fn is_fixed_point(&self) -> bool {
+        self.period == 1
+    }
+}
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_fixed_point() {
+            write!(f, "fixed point after {} steps", self.start)
+        } else {
+            write!(
+                f,
+                "entered a cycle of period {} after {} steps",
+                self.period, self.start
+            )
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CycleDetector {
+    /// wrapping sum of `mix` over all nodes, `None` until it's worked out again
+    hash: Option<u64>,
+    /// the clock divider and pulse periods in the graph, worked out with `hash`
+    periods: Vec<u64>,
+    /// `Graph::edits` the history belongs to, the states mean something else after an edit
+    edits: u64,
+    /// the last tick each remembered hash was seen at
+    seen: HashMap<u64, u64>,
+    /// `(hash, tick)` oldest first
+    order: VecDeque<(u64, u64)>,
+    cycle: Option<Cycle>,
+}
+
+fn mix(node: usize, state: u32, registers: &[i32; REGISTERS]) -> u64 {
+    let hash = splitmix64(((node as u64) << 32) ^ state as u64);
+    // most nodes never touch their registers
+    if *registers == [0; REGISTERS] {
+        return hash;
+    }
+    registers
+        .iter()
+        .fold(hash, |hash, &a| splitmix64(hash ^ a as u32 as u64))
+}
+
+/// the period a node's state depends on the tick with, if any
+fn clock_period(node: &Node) -> Option<u64> {
+    match node.kind {
+        NodeKind::Pulse { every, .. } if !node.pinned && every > 1 => Some(every as u64),
+        _ => (node.period > 1).then_some(node.period as u64),
+    }
+}
+
+impl CycleDetector {
+    /// forgets the history, for when states were changed by hand
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// the cycle the automaton is in, if it is in one
+    pub fn cycle(&self) -> Option<Cycle> {
+        self.cycle
+    }
+
+    /// updates the hash for a node that went from `old` to `new` state and registers
+    pub fn changed(
+        &mut self,
+        node: usize,
+        old: (u32, &[i32; REGISTERS]),
+        new: (u32, &[i32; REGISTERS]),
+    ) {
+        if let Some(hash) = &mut self.hash {
+            *hash = hash
+                .wrapping_add(mix(node, new.0, new.1))
+                .wrapping_sub(mix(node, old.0, old.1));
+        }
+    }
+
+    /// records the states a step starts from if the history was thrown away,
+    /// otherwise they are already in there
+    pub fn before_step(&mut self, graph: &Graph, tick: u64) {
+        if self.edits != graph.edits() || self.order.is_empty() {
+            self.record(graph, tick);
+        }
+    }
+
+    /// remembers the `write` states and registers of `graph` as the ones at `tick`
+    pub fn record(&mut self, graph: &Graph, tick: u64) {
+        if self.edits != graph.edits() {
+            self.reset();
+            self.edits = graph.edits();
+        }
+        if self.hash.is_none() {
+            self.hash = Some(graph.nodes.iter().enumerate().fold(0, |sum, (i, node)| {
+                sum.wrapping_add(mix(i, node.write, &node.registers))
+            }));
+            self.periods = graph.nodes.iter().filter_map(clock_period).collect();
+            self.periods.sort_unstable();
+            self.periods.dedup();
+        }
+        // the same states at another point of a clock go on differently
+        let hash = self
+            .periods
+            .iter()
+            .fold(self.hash.unwrap(), |sum, &period| {
+                sum.wrapping_add(splitmix64(splitmix64(period) ^ (tick % period)))
+            });
+
+        self.cycle = self.seen.get(&hash).map(|&seen| {
+            let period = tick - seen;
+            match self.cycle {
+                Some(cycle) if cycle.period == period => cycle,
+                _ => Cycle {
+                    period,
+                    start: seen,
+                },
+            }
+        });
+
+        self.seen.insert(hash, tick);
+        self.order.push_back((hash, tick));
+        if self.order.len() > HISTORY {
+            let (hash, tick) = self.order.pop_front().unwrap();
+            if self.seen.get(&hash) == Some(&tick) {
+                self.seen.remove(&hash);
+            }
+        }
+    }
+}
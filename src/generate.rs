@@ -0,0 +1,225 @@
+//! Graphs of common shapes, so regular patches don't have to be built node by node.
+//!
+//! Every node starts in `state`. Edges go both ways since a node only counts the nodes it has
+//! edges to. The graphs are laid out near the origin with `SPACING` between neighbours,
+//! `Graph::append` puts one into another graph somewhere else.
+
+use std::f32::consts::TAU;
+
+use crate::graph::{Graph, Node};
+use crate::rng::Rng;
+use crate::vec2::Vec2;
+
+/// distance between neighbouring nodes
+pub const SPACING: f32 = 80.0;
+
+/// which nodes around it a node in a grid is connected to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// the 4 next to it
+    VonNeumann,
+    /// the 8 next to it and diagonally
+    Moore,
+}
+
+impl Graph {
+    /// `n` nodes in a row
+    pub fn line(n: usize, state: u32) -> Self {
+        let mut graph = nodes((0..n).map(|i| Vec2::new(i as f32 * SPACING, 0.0)), state);
+        for i in 1..n {
+            connect(&mut graph, i - 1, i);
+        }
+        graph
+    }
+
+    /// `n` nodes in a circle, each connected to the ones before and after it
+    pub fn ring(n: usize, state: u32) -> Self {
+        let mut graph = nodes(circle(n), state);
+        for i in 0..n {
+            connect(&mut graph, i, (i + 1) % n);
+        }
+        graph
+    }
+
+    /// a `width` by `height` grid
+    pub fn grid(width: usize, height: usize, neighbourhood: Neighbourhood, state: u32) -> Self {
+        lattice(width, height, neighbourhood, false, state)
+    }
+
+    /// a grid whose edges wrap around to the other side
+    pub fn torus(width: usize, height: usize, neighbourhood: Neighbourhood, state: u32) -> Self {
+        lattice(width, height, neighbourhood, true, state)
+    }
+
+    /// a `width` by `height` hexagonal lattice, every node has up to 6 neighbours
+    pub fn hex(width: usize, height: usize, state: u32) -> Self {
+        let positions = (0..height).flat_map(|y| {
+            (0..width).map(move |x| {
+                // odd rows are shifted right by half a node
+                let shift = if y % 2 == 1 { 0.5 } else { 0.0 };
+                Vec2::new(
+                    (x as f32 + shift) * SPACING,
+                    y as f32 * SPACING * 3f32.sqrt() / 2.0,
+                )
+            })
+        });
+        let mut graph = nodes(positions, state);
+        let index = |x: usize, y: usize| y * width + x;
+        for y in 0..height {
+            for x in 0..width {
+                if x + 1 < width {
+                    connect(&mut graph, index(x, y), index(x + 1, y));
+                }
+                if y + 1 < height {
+                    // every other row is shifted, so the second node below is on the left
+                    // under even rows and on the right under odd ones
+                    connect(&mut graph, index(x, y), index(x, y + 1));
+                    let other = if y % 2 == 1 { x + 1 } else { x.wrapping_sub(1) };
+                    if other < width {
+                        connect(&mut graph, index(x, y), index(other, y + 1));
+                    }
+                }
+            }
+        }
+        graph
+    }
+
+    /// `n` nodes in a circle where every pair is connected with `probability`
+    pub fn erdos_renyi(n: usize, probability: f64, seed: u64, state: u32) -> Self {
+        let rng = Rng::new(seed, 0);
+        let mut graph = nodes(circle(n), state);
+        for i in 0..n {
+            for j in i + 1..n {
+                if rng.roll(i, j) < probability {
+                    connect(&mut graph, i, j);
+                }
+            }
+        }
+        graph
+    }
+
+    /// a Watts-Strogatz small world: a ring where every node is connected to the `k` nodes on
+    /// either side, then each edge is moved to a random node with `probability`
+    pub fn small_world(n: usize, k: usize, probability: f64, seed: u64, state: u32) -> Self {
+        let rng = Rng::new(seed, 0);
+        let mut graph = nodes(circle(n), state);
+        for i in 0..n {
+            for d in 1..=k.min(n.saturating_sub(1) / 2) {
+                let mut j = (i + d) % n;
+                if rng.roll(i, d) < probability {
+                    // a few tries for a node that isn't this one or a neighbour already
+                    for attempt in 0..8 {
+                        let other = (rng.roll(i, n * (attempt + 1) + d) * n as f64) as usize;
+                        if other != i && graph.edge_weight(i, other).is_none() {
+                            j = other;
+                            break;
+                        }
+                    }
+                }
+                connect(&mut graph, i, j);
+            }
+        }
+        graph
+    }
+
+    /// a tree `depth` levels below the root where every node has `branching` children
+    pub fn tree(depth: usize, branching: usize, state: u32) -> Self {
+        let mut graph = Graph::new();
+        let mut level = vec![];
+        let leaves = branching.pow(depth as u32);
+        for d in 0..=depth {
+            let count = branching.pow(d as u32);
+            // the last level is `SPACING` apart, the ones above spread over the same width
+            let width = leaves.max(1) as f32 * SPACING;
+            let start = graph.nodes.len();
+            for i in 0..count {
+                let x = (i as f32 + 0.5) * width / count as f32 - width / 2.0;
+                graph.add_node(Node::new(
+                    state,
+                    state,
+                    vec![],
+                    Vec2::new(x, d as f32 * SPACING),
+                ));
+                if d > 0 {
+                    connect(&mut graph, level[i / branching], start + i);
+                }
+            }
+            level = (start..graph.nodes.len()).collect();
+        }
+        graph
+    }
+
+    /// adds the nodes and edges of `other` moved by `offset`, returns the new nodes
+    pub fn append(&mut self, other: &Graph, offset: Vec2) -> Vec<usize> {
+        let start = self.nodes.len();
+        for node in &other.nodes {
+            let mut node = node.clone();
+            node.edges = vec![];
+            node.position += offset;
+            self.add_node(node);
+        }
+        for (i, node) in other.nodes.iter().enumerate() {
+            for edge in &node.edges {
+                self.add_weighted_edge(start + i, start + edge.to, edge.weight);
+            }
+        }
+        (start..self.nodes.len()).collect()
+    }
+}
+
+fn nodes(positions: impl Iterator<Item = Vec2>, state: u32) -> Graph {
+    let mut graph = Graph::new();
+    for position in positions {
+        graph.add_node(Node::new(state, state, vec![], position));
+    }
+    graph
+}
+
+/// `n` positions on a circle `SPACING` apart
+fn circle(n: usize) -> impl Iterator<Item = Vec2> {
+    let radius = n as f32 * SPACING / TAU;
+    (0..n).map(move |i| {
+        let angle = i as f32 * TAU / n as f32;
+        Vec2::new(angle.cos(), angle.sin()) * radius
+    })
+}
+
+/// edges both ways between `u` and `v`
+fn connect(graph: &mut Graph, u: usize, v: usize) {
+    if u != v {
+        graph.add_edge(u, v);
+        graph.add_edge(v, u);
+    }
+}
+
+fn lattice(
+    width: usize,
+    height: usize,
+    neighbourhood: Neighbourhood,
+    wrap: bool,
+    state: u32,
+) -> Graph {
+    let positions =
+        (0..height).flat_map(|y| (0..width).map(move |x| Vec2::new(x as f32, y as f32) * SPACING));
+    let mut graph = nodes(positions, state);
+    let offsets: &[(isize, isize)] = match neighbourhood {
+        // each pair once, `connect` adds both directions
+        Neighbourhood::VonNeumann => &[(1, 0), (0, 1)],
+        Neighbourhood::Moore => &[(1, 0), (0, 1), (1, 1), (-1, 1)],
+    };
+    for y in 0..height {
+        for x in 0..width {
+            for &(dx, dy) in offsets {
+                let (mut nx, mut ny) = (x as isize + dx, y as isize + dy);
+                if wrap {
+                    nx = nx.rem_euclid(width as isize);
+                    ny = ny.rem_euclid(height as isize);
+                }
+                if (0..width as isize).contains(&nx) && (0..height as isize).contains(&ny) {
+                    connect(&mut graph, y * width + x, ny as usize * width + nx as usize);
+                }
+            }
+        }
+    }
+    graph
+}
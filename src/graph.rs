@@ -1,37 +1,367 @@
-use crate::{
-    app::{Note, UiState},
-    vec2::Vec2,
-};
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+use crate::automaton::RulesetError;
+use crate::component::Member;
+use crate::{note::Note, vec2::Vec2};
+
+/// an edge to the node at `to`, counted `weight` times by patterns.
+/// negative weights make inhibitory edges.
+/// in `Node::incoming` the same edge is stored with `to` pointing back at where it comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "EdgeRepr")]
+pub struct Edge {
+    pub to: usize,
+    pub weight: i32,
+}
+
+impl Edge {
+    pub fn new(to: usize, weight: i32) -> Self {
+        Self { to, weight }
+    }
+}
+
+// worlds saved before edges had weights store plain indices
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum EdgeRepr {
+    Plain(usize),
+    Weighted { to: usize, weight: i32 },
+}
+
+impl From<EdgeRepr> for Edge {
+    fn from(value: EdgeRepr) -> Self {
+        match value {
+            EdgeRepr::Plain(to) => Edge::new(to, 1),
+            EdgeRepr::Weighted { to, weight } => Edge::new(to, weight),
+        }
+    }
+}
+
+/// which edges of a node patterns count over
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Direction {
+    /// the nodes in `Node::edges`, the only neighbours before edges had a direction
+    Outgoing,
+    /// the nodes that have this node in their `edges`
+    Incoming,
+    /// both of the above, a node connected both ways is counted twice
+    Both,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Node {
     pub read: u32,
     pub write: u32,
-    pub edges: Vec<usize>,
+    pub edges: Vec<Edge>,
+    /// reverse adjacency kept up to date by `Graph`, not saved
+    #[serde(skip)]
+    pub incoming: Vec<Edge>,
     pub position: Vec2,
     pub note: Option<Note>,
+    /// the node only updates on ticks where `tick % period == phase`
+    #[serde(default = "default_period")]
+    pub period: u32,
+    #[serde(default)]
+    pub phase: u32,
+    /// counters for rules to test and change, see `automaton::Action`
+    #[serde(default)]
+    pub registers: [i32; REGISTERS],
+    /// keeps its state whatever the rules say, a constant source or sink
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub kind: NodeKind,
+    /// the instance of a component the node is part of
+    #[serde(default)]
+    pub member: Option<Member>,
+}
+
+/// where a node's next state comes from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NodeKind {
+    /// the rules of its state
+    #[default]
+    Ruled,
+    /// a pulse generator, `state` on ticks where `tick % every == phase` and `rest` otherwise
+    Pulse {
+        state: u32,
+        rest: u32,
+        every: u32,
+        phase: u32,
+    },
+}
+
+/// number of registers every node has
+pub const REGISTERS: usize = 4;
+
+fn default_period() -> u32 {
+    1
 }
 
 impl Node {
-    pub fn new(read: u32, write: u32, edges: Vec<usize>, position: Vec2) -> Self {
+    pub fn new(read: u32, write: u32, edges: Vec<Edge>, position: Vec2) -> Self {
         Self {
             read,
             write,
             edges,
+            incoming: vec![],
             position,
             note: None,
+            period: 1,
+            phase: 0,
+            registers: [0; REGISTERS],
+            pinned: false,
+            kind: NodeKind::Ruled,
+            member: None,
+        }
+    }
+
+    /// the state the node takes at `tick` without asking the rules, if it doesn't ask them
+    pub fn forced_state(&self, tick: u64) -> Option<u32> {
+        match self.kind {
+            _ if self.pinned => Some(self.read),
+            NodeKind::Ruled => None,
+            NodeKind::Pulse {
+                state,
+                rest,
+                every,
+                phase,
+            } => {
+                let every = every.max(1) as u64;
+                Some(if tick % every == phase as u64 % every {
+                    state
+                } else {
+                    rest
+                })
+            }
         }
     }
+
+    /// the states the node is in or switches to by itself, the rules need all of them
+    pub fn states(&self) -> impl Iterator<Item = u32> {
+        let pulse = match self.kind {
+            NodeKind::Pulse { state, rest, .. } => Some([state, rest]),
+            NodeKind::Ruled => None,
+        };
+        [self.read, self.write]
+            .into_iter()
+            .chain(pulse.into_iter().flatten())
+    }
+
+    /// whether the node can change without its neighbours changing
+    pub fn is_restless(&self) -> bool {
+        self.period > 1 || (!self.pinned && self.kind != NodeKind::Ruled)
+    }
+
+    /// whether the clock divider lets the node update at `tick`
+    pub fn updates_at(&self, tick: u64) -> bool {
+        if self.period <= 1 {
+            return true;
+        }
+        let period = self.period as u64;
+        tick % period == self.phase as u64 % period
+    }
+}
+
+/// names a node for as long as it exists. indices into `Graph::nodes` change when other nodes
+/// are removed, ids don't, and a removed node's id is not given to the nodes added after it
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct NodeId {
+    pub slot: u32,
+    /// tells apart the nodes that had the slot
+    pub generation: u32,
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+/// edges hold indices into `nodes` so stepping doesn't have to look ids up,
+/// in the save file they hold `NodeId::slot`
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "GraphData", into = "GraphData")]
 pub struct Graph {
     pub nodes: Vec<Node>,
+    /// the id of each node in `nodes`
+    ids: Vec<NodeId>,
+    /// by `NodeId::slot`
+    slots: Vec<Slot>,
+    /// slots without a node, reused by `add_node`
+    free: Vec<u32>,
+    distances: DistanceCache,
+    edits: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Slot {
+    /// of the node in the slot or the last one that was
+    generation: u32,
+    /// the generation `add_node` gives next, past every one given out so a node that was
+    /// put back can't bring an id back that a later node had
+    next: u32,
+    /// index into `Graph::nodes`
+    node: Option<usize>,
+}
+
+impl Slot {
+    fn fill(&mut self, generation: u32, node: usize) {
+        self.generation = generation;
+        self.next = self.next.max(generation.wrapping_add(1));
+        self.node = Some(node);
+    }
+}
+
+/// breadth first layers around nodes, `layers[d]` holds the nodes at distance `d + 1`.
+/// thrown away whenever the edges change
+#[derive(Debug, Default)]
+struct DistanceCache(RwLock<HashMap<(usize, Direction), Layers>>);
+
+#[derive(Debug)]
+struct Layers {
+    depth: u32,
+    layers: Vec<Vec<usize>>,
+}
+
+impl Clone for DistanceCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GraphData {
+    nodes: Vec<Node>,
+    /// the id of each node, worlds saved before ids have none and edges pointing at indices
+    #[serde(default)]
+    ids: Vec<NodeId>,
+    /// the free slots with the generation their next node gets
+    #[serde(default)]
+    free: Vec<NodeId>,
+}
+
+impl TryFrom<GraphData> for Graph {
+    type Error = RulesetError;
+
+    fn try_from(value: GraphData) -> Result<Self, RulesetError> {
+        let mut graph = Graph {
+            nodes: value.nodes,
+            ..Graph::new()
+        };
+        let len = graph.nodes.len();
+        // edges hold slots, with the ids wrong they would lead to the wrong nodes
+        if value.ids.is_empty() {
+            graph.set_ids(&first_ids(len), &[])?;
+        } else if value.ids.len() != len {
+            return Err(RulesetError::IdsMismatch {
+                ids: value.ids.len(),
+                nodes: len,
+            });
+        } else {
+            graph.set_ids(&value.ids, &value.free)?;
+        }
+        for (i, node) in graph.nodes.iter_mut().enumerate() {
+            for edge in node.edges.iter_mut() {
+                edge.to = graph.slots.get(edge.to).and_then(|a| a.node).ok_or(
+                    RulesetError::EdgeOutOfRange {
+                        node: i,
+                        to: edge.to,
+                    },
+                )?;
+            }
+        }
+        graph.rebuild_incoming();
+        Ok(graph)
+    }
+}
+
+/// the ids of a graph with `len` nodes that never had one removed
+fn first_ids(len: usize) -> Vec<NodeId> {
+    (0..len as u32)
+        .map(|slot| NodeId {
+            slot,
+            generation: 0,
+        })
+        .collect()
+}
+
+impl From<Graph> for GraphData {
+    fn from(value: Graph) -> Self {
+        let mut nodes = value.nodes;
+        for node in nodes.iter_mut() {
+            for edge in node.edges.iter_mut() {
+                edge.to = value.ids[edge.to].slot as usize;
+            }
+        }
+        let free = value
+            .free
+            .iter()
+            .map(|&slot| NodeId {
+                slot,
+                generation: value.slots[slot as usize].next,
+            })
+            .collect();
+        GraphData {
+            nodes,
+            ids: value.ids,
+            free,
+        }
+    }
 }
 
 impl Graph {
     pub fn new() -> Self {
-        Self { nodes: Vec::new() }
+        Self {
+            nodes: Vec::new(),
+            ids: Vec::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            distances: DistanceCache::default(),
+            edits: 0,
+        }
+    }
+
+    /// the id of the node at `index`
+    pub fn id(&self, index: usize) -> NodeId {
+        self.ids[index]
+    }
+
+    /// where the node with `id` is now, `None` if it was removed
+    pub fn index(&self, id: NodeId) -> Option<usize> {
+        self.slots
+            .get(id.slot as usize)
+            .filter(|a| a.generation == id.generation)
+            .and_then(|a| a.node)
+    }
+
+    /// gives the nodes `ids` and frees the `free` slots and the ones no node has,
+    /// fails if two nodes would share a slot
+    fn set_ids(&mut self, ids: &[NodeId], free: &[NodeId]) -> Result<(), RulesetError> {
+        let size = ids.iter().chain(free).map(|a| a.slot as usize + 1).max();
+        self.slots = vec![Slot::default(); size.unwrap_or(0)];
+        for (i, id) in ids.iter().enumerate() {
+            let slot = &mut self.slots[id.slot as usize];
+            if slot.node.is_some() {
+                return Err(RulesetError::DuplicateSlot { slot: id.slot });
+            }
+            slot.fill(id.generation, i);
+        }
+        let mut listed = vec![false; self.slots.len()];
+        let mut saved = vec![];
+        for id in free {
+            let slot = &mut self.slots[id.slot as usize];
+            if slot.node.is_none() && !listed[id.slot as usize] {
+                listed[id.slot as usize] = true;
+                slot.next = id.generation;
+                saved.push(id.slot);
+            }
+        }
+        self.ids = ids.to_vec();
+        // slots that weren't saved as free go first so `add_node` reuses them last
+        self.free = (0..self.slots.len())
+            .filter(|&i| self.slots[i].node.is_none() && !listed[i])
+            .map(|i| i as u32)
+            .collect();
+        self.free.extend(saved);
+        Ok(())
     }
 
     pub fn copy(&self, selection: &[usize]) -> Self {
@@ -45,15 +375,16 @@ impl Graph {
         for selected in selection {
             let mut new_node = self.nodes[*selected].clone();
             new_node.edges = vec![];
+            new_node.incoming = vec![];
             new_graph.add_node(new_node);
             indexes[*selected] = Some(new_graph.nodes.len() - 1)
         }
         println!("{:?}", indexes);
         for (i, &selected) in selection.iter().enumerate() {
-            for j in &self.nodes[selected].edges {
-                if let Some(new_index) = indexes[*j] {
+            for edge in &self.nodes[selected].edges {
+                if let Some(new_index) = indexes[edge.to] {
                     println!("{i}");
-                    new_graph.add_edge(i, new_index);
+                    new_graph.add_weighted_edge(i, new_index, edge.weight);
                 }
             }
         }
@@ -96,49 +427,272 @@ impl Graph {
     //         }
     //     }
     // }
-    pub fn add_node(&mut self, node: Node) {
-        self.nodes.push(node)
+    /// adds a node, edges to nodes that don't exist yet are not counted as incoming
+    /// until `rebuild_incoming` is called
+    pub fn add_node(&mut self, node: Node) -> NodeId {
+        let id = match self.free.pop() {
+            Some(slot) => NodeId {
+                slot,
+                generation: self.slots[slot as usize].next,
+            },
+            None => {
+                self.slots.push(Slot::default());
+                NodeId {
+                    slot: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.push_node(id, node);
+        id
     }
-    pub fn remove_node_from_app(&mut self, idx: usize, ui: &mut UiState) {
-        self.nodes.swap_remove(idx);
-        let len = self.nodes.len();
+
+    /// adds `node` back with the id it had before it was removed, or with a new one if its slot
+    /// was taken since
+    pub fn restore_node(&mut self, id: NodeId, node: Node) -> NodeId {
+        if self
+            .slots
+            .get(id.slot as usize)
+            .is_none_or(|a| a.node.is_some())
+        {
+            return self.add_node(node);
+        }
+        self.free.retain(|&a| a != id.slot);
+        self.push_node(id, node);
+        id
+    }
+
+    /// adds `node` at the end with `id`, whose slot has to be empty and not in `free`
+    fn push_node(&mut self, id: NodeId, mut node: Node) {
+        let idx = self.nodes.len();
+        node.incoming = vec![];
+        self.nodes.push(node);
+        self.slots[id.slot as usize].fill(id.generation, idx);
+        self.ids.push(id);
+        self.edited();
+        for edge in self.nodes[idx].edges.clone() {
+            if edge.to < self.nodes.len() {
+                self.nodes[edge.to]
+                    .incoming
+                    .push(Edge::new(idx, edge.weight))
+            }
+        }
+    }
+
+    /// recomputes every `Node::incoming` from the outgoing edges
+    pub fn rebuild_incoming(&mut self) {
+        self.edited();
         for node in self.nodes.iter_mut() {
-            node.edges.retain(|a| *a != idx);
-            node.edges = node
-                .edges
-                .iter()
-                .map(|a| if *a == len { idx } else { *a })
-                .collect();
-        }
-        ui.selected = ui
-            .selected
+            node.incoming = vec![];
+        }
+        for u in 0..self.nodes.len() {
+            for edge in self.nodes[u].edges.clone() {
+                self.nodes[edge.to].incoming.push(Edge::new(u, edge.weight))
+            }
+        }
+    }
+
+    pub fn neighbours(&self, node: usize, direction: Direction) -> impl Iterator<Item = &Edge> {
+        let node = &self.nodes[node];
+        let (a, b): (&[Edge], &[Edge]) = match direction {
+            Direction::Outgoing => (&node.edges, &[]),
+            Direction::Incoming => (&[], &node.incoming),
+            Direction::Both => (&node.edges, &node.incoming),
+        };
+        a.iter().chain(b)
+    }
+
+    /// calls `f` with the nodes at distance 1 up to `depth` from `node`, one slice per distance.
+    /// the breadth first search is cached until the edges change
+    pub fn layers<R>(
+        &self,
+        node: usize,
+        direction: Direction,
+        depth: u32,
+        f: impl FnOnce(&[Vec<usize>]) -> R,
+    ) -> R {
+        let key = (node, direction);
+        {
+            let cache = self.distances.0.read().unwrap();
+            if let Some(cached) = cache.get(&key) {
+                if cached.depth >= depth {
+                    return f(&cached.layers[..cached.layers.len().min(depth as usize)]);
+                }
+            }
+        }
+
+        let mut visited = HashSet::from([node]);
+        let mut layers: Vec<Vec<usize>> = vec![];
+        let mut frontier = vec![node];
+        while (layers.len() as u32) < depth && !frontier.is_empty() {
+            let mut next = vec![];
+            for &u in &frontier {
+                for edge in self.neighbours(u, direction) {
+                    if visited.insert(edge.to) {
+                        next.push(edge.to)
+                    }
+                }
+            }
+            if !next.is_empty() {
+                layers.push(next.clone());
+            }
+            frontier = next;
+        }
+        // nothing more to find, deeper searches would give the same layers
+        let depth = if frontier.is_empty() { u32::MAX } else { depth };
+
+        let result = f(&layers);
+        self.distances
+            .0
+            .write()
+            .unwrap()
+            .insert(key, Layers { depth, layers });
+        result
+    }
+
+    /// forgets the cached distances, needed after editing `Node::edges` by hand
+    pub fn invalidate_distances(&self) {
+        self.distances.0.write().unwrap().clear();
+    }
+
+    /// how many times nodes or edges were added, removed or reweighted through `Graph`,
+    /// for noticing that something computed from the graph is out of date
+    pub fn edits(&self) -> u64 {
+        self.edits
+    }
+
+    fn edited(&mut self) {
+        self.edits += 1;
+        self.invalidate_distances();
+    }
+    /// removes the node at `idx` and its edges and returns it with them, the last node moves
+    /// into `idx`. only touches the neighbours of the two nodes, ids of the other nodes stay
+    /// the same
+    pub fn remove_node(&mut self, idx: usize) -> Node {
+        self.edited();
+        let edges = std::mem::take(&mut self.nodes[idx].edges);
+        let incoming = std::mem::take(&mut self.nodes[idx].incoming);
+        for edge in &edges {
+            self.nodes[edge.to].incoming.retain(|a| a.to != idx);
+        }
+        for edge in &incoming {
+            self.nodes[edge.to].edges.retain(|a| a.to != idx);
+        }
+
+        let id = self.ids.swap_remove(idx);
+        let mut removed = self.nodes.swap_remove(idx);
+        removed.edges = edges;
+        removed.incoming = incoming;
+        self.slots[id.slot as usize].node = None;
+        self.free.push(id.slot);
+
+        let last = self.nodes.len();
+        if idx != last {
+            self.slots[self.ids[idx].slot as usize].node = Some(idx);
+            self.renumber(last, idx);
+        }
+        removed
+    }
+
+    /// puts back a node `remove_node(idx)` returned, with the id it had, into a graph that is
+    /// as the removal left it. the node that moved into `idx` goes back to the end
+    pub(crate) fn insert_node(&mut self, idx: usize, id: NodeId, node: Node) {
+        self.edited();
+        self.slots[id.slot as usize].fill(id.generation, idx);
+        self.free.retain(|&a| a != id.slot);
+
+        let last = self.nodes.len();
+        self.nodes.push(node);
+        self.ids.push(id);
+        if idx != last {
+            self.nodes.swap(idx, last);
+            self.ids.swap(idx, last);
+            self.slots[self.ids[last].slot as usize].node = Some(last);
+            self.renumber(idx, last);
+        }
+        let node = &self.nodes[idx];
+        let edges: Vec<Edge> = node.edges.iter().filter(|a| a.to != idx).copied().collect();
+        let incoming: Vec<Edge> = node
+            .incoming
             .iter()
-            .map(|a| if *a == len { idx } else { *a })
+            .filter(|a| a.to != idx)
+            .copied()
             .collect();
+        for edge in edges {
+            self.nodes[edge.to]
+                .incoming
+                .push(Edge::new(idx, edge.weight));
+        }
+        for edge in incoming {
+            self.nodes[edge.to].edges.push(Edge::new(idx, edge.weight));
+        }
     }
-    pub fn remove_node(&mut self, idx: usize) {
-        self.nodes.swap_remove(idx);
-        let len = self.nodes.len();
-        for node in self.nodes.iter_mut() {
-            node.edges.retain(|a| *a != idx);
-            node.edges = node
-                .edges
-                .iter()
-                .map(|a| if *a == len { idx } else { *a })
-                .collect();
+
+    /// points the edges of the node now at `to`, which used to be at `from`, and its
+    /// neighbours' edges back to it at `to`
+    fn renumber(&mut self, from: usize, to: usize) {
+        let moved = &mut self.nodes[to];
+        // edges to itself first, then the neighbours' edges back to it
+        for edge in moved.edges.iter_mut().chain(moved.incoming.iter_mut()) {
+            if edge.to == from {
+                edge.to = to
+            }
+        }
+        for i in 0..self.nodes[to].edges.len() {
+            let next = self.nodes[to].edges[i].to;
+            for edge in self.nodes[next].incoming.iter_mut() {
+                if edge.to == from {
+                    edge.to = to
+                }
+            }
+        }
+        for i in 0..self.nodes[to].incoming.len() {
+            let previous = self.nodes[to].incoming[i].to;
+            for edge in self.nodes[previous].edges.iter_mut() {
+                if edge.to == from {
+                    edge.to = to
+                }
+            }
         }
     }
 
     pub fn add_edge(&mut self, u: usize, v: usize) -> bool {
-        if !self.nodes[u].edges.contains(&v) {
-            self.nodes[u].edges.push(v);
+        self.add_weighted_edge(u, v, 1)
+    }
+
+    pub fn add_weighted_edge(&mut self, u: usize, v: usize, weight: i32) -> bool {
+        if self.edge_weight(u, v).is_none() {
+            self.nodes[u].edges.push(Edge::new(v, weight));
+            self.nodes[v].incoming.push(Edge::new(u, weight));
+            self.edited();
             true
         } else {
             false
         }
     }
 
+    /// the weight of the edge from `u` to `v`, if there is one
+    pub fn edge_weight(&self, u: usize, v: usize) -> Option<i32> {
+        self.nodes[u]
+            .edges
+            .iter()
+            .find(|a| a.to == v)
+            .map(|a| a.weight)
+    }
+
+    pub fn set_edge_weight(&mut self, u: usize, v: usize, weight: i32) {
+        self.edits += 1;
+        for edge in self.nodes[u].edges.iter_mut().filter(|a| a.to == v) {
+            edge.weight = weight
+        }
+        for edge in self.nodes[v].incoming.iter_mut().filter(|a| a.to == u) {
+            edge.weight = weight
+        }
+    }
+
     pub fn remove_edge(&mut self, u: usize, v: usize) {
-        self.nodes[u].edges.retain(|a| *a != v);
+        self.nodes[u].edges.retain(|a| a.to != v);
+        self.nodes[v].incoming.retain(|a| a.to != u);
+        self.edited();
     }
 }
@@ -0,0 +1,196 @@
+//! The steps the automaton took, so they can be taken back.
+//!
+//! A step swaps `read` and `write` and writes the new states, so after it `read` holds what
+//! `write` was before. Going back only needs the old `read` of the nodes the step changed,
+//! plus the old registers if rules changed them. Steps where rules changed the topology also
+//! keep the changes, which are taken back in reverse before the states. Going forward again
+//! just steps, which comes out the same.
+
+use std::collections::VecDeque;
+
+use crate::graph::{Graph, Node, NodeId, REGISTERS};
+
+/// how many node changes the history holds, old steps are forgotten past that
+pub const CAPACITY: usize = 1 << 22;
+
+/// a change to the topology made by a rule, with indices from when it was made
+#[derive(Clone, Debug)]
+pub(crate) enum Rewrite {
+    /// a node was added at the end
+    Added,
+    Connected {
+        from: u32,
+        to: u32,
+    },
+    Disconnected {
+        from: u32,
+        to: u32,
+        weight: i32,
+    },
+    /// `Graph::remove_node(index)` returned `node`
+    Removed {
+        index: u32,
+        id: NodeId,
+        node: Node,
+    },
+}
+
+impl Rewrite {
+    fn undo(self, graph: &mut Graph) {
+        match self {
+            Rewrite::Added => {
+                graph.remove_node(graph.nodes.len() - 1);
+            }
+            Rewrite::Connected { from, to } => graph.remove_edge(from as usize, to as usize),
+            Rewrite::Disconnected { from, to, weight } => {
+                graph.add_weighted_edge(from as usize, to as usize, weight);
+            }
+            Rewrite::Removed { index, id, node } => graph.insert_node(index as usize, id, node),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Rewrite::Removed { node, .. } => 1 + node.edges.len() + node.incoming.len(),
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Diff {
+    /// tick the step started at
+    tick: u64,
+    /// `(node, read)` from before the step, indices from before the topology changes
+    reads: Vec<(u32, u32)>,
+    /// `(node, registers)` from before the step, only for nodes whose registers changed
+    registers: Vec<(u32, [i32; REGISTERS])>,
+    /// in the order they were made
+    rewrites: Vec<Rewrite>,
+}
+
+impl Diff {
+    /// what a step from `tick` that looked at `nodes` changed, before rules changed the
+    /// topology
+    pub(crate) fn new(tick: u64, nodes: &[usize], before: &Before, graph: &Graph) -> Self {
+        let mut diff = Diff {
+            tick,
+            reads: vec![],
+            registers: vec![],
+            rewrites: vec![],
+        };
+        // `read` is now what `write` was, so the node changed if either differs
+        for (&node, &read) in nodes.iter().zip(&before.reads) {
+            let after = &graph.nodes[node];
+            if read != after.read || after.read != after.write {
+                diff.reads.push((node as u32, read));
+            }
+        }
+        for (&node, registers) in nodes.iter().zip(before.registers.iter().flatten()) {
+            if *registers != graph.nodes[node].registers {
+                diff.registers.push((node as u32, *registers));
+            }
+        }
+        diff
+    }
+
+    fn size(&self) -> usize {
+        self.reads.len()
+            + self.registers.len()
+            + self.rewrites.iter().map(Rewrite::size).sum::<usize>()
+    }
+}
+
+/// what the nodes a step looks at were like before it
+pub(crate) struct Before {
+    reads: Vec<u32>,
+    /// only kept if rules can change registers
+    registers: Option<Vec<[i32; REGISTERS]>>,
+}
+
+impl Before {
+    pub(crate) fn new(graph: &Graph, nodes: &[usize], registers: bool) -> Self {
+        Self {
+            reads: nodes.iter().map(|a| graph.nodes[*a].read).collect(),
+            registers: registers.then(|| nodes.iter().map(|a| graph.nodes[*a].registers).collect()),
+        }
+    }
+
+    /// the registers of the nodes in the order they were given, if they were kept
+    pub(crate) fn registers(&self) -> Option<&[[i32; REGISTERS]]> {
+        self.registers.as_deref()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    past: VecDeque<Diff>,
+    /// node changes in `past`
+    size: usize,
+    /// steps that were taken back and can be taken again
+    future: u64,
+    /// `Graph::edits` after the last step, other edits make the recorded indices meaningless
+    edits: u64,
+}
+
+impl History {
+    /// forgets the steps taken back, stepping from here would go somewhere else
+    pub fn forget_future(&mut self) {
+        self.future = 0;
+    }
+
+    /// how many steps were taken back
+    pub fn future(&self) -> u64 {
+        self.future
+    }
+
+    /// the oldest tick that can be gone back to and the newest one that can be gone forward to
+    pub fn ticks(&self, tick: u64) -> (u64, u64) {
+        (
+            self.past.front().map_or(tick, |a| a.tick),
+            tick + self.future,
+        )
+    }
+
+    /// throws everything away if the graph was edited since the last step
+    pub(crate) fn check(&mut self, graph: &Graph) {
+        if self.edits != graph.edits() {
+            *self = Self::default();
+            self.edits = graph.edits();
+        }
+    }
+
+    /// remembers a step, `rewrites` are the topology changes made after `diff`
+    pub(crate) fn record(&mut self, mut diff: Diff, rewrites: Vec<Rewrite>, graph: &Graph) {
+        diff.rewrites = rewrites;
+        self.future = self.future.saturating_sub(1);
+        self.size += diff.size();
+        self.past.push_back(diff);
+        while self.size > CAPACITY && self.past.len() > 1 {
+            let oldest = self.past.pop_front().unwrap();
+            self.size -= oldest.size();
+        }
+        self.edits = graph.edits();
+    }
+
+    /// undoes the last step, returns the tick it started at
+    pub(crate) fn back(&mut self, graph: &mut Graph) -> Option<u64> {
+        self.check(graph);
+        let diff = self.past.pop_back()?;
+        self.size -= diff.size();
+        for rewrite in diff.rewrites.into_iter().rev() {
+            rewrite.undo(graph);
+        }
+        for (node, read) in diff.reads {
+            let node = &mut graph.nodes[node as usize];
+            node.write = node.read;
+            node.read = read;
+        }
+        for (node, registers) in diff.registers {
+            graph.nodes[node as usize].registers = registers;
+        }
+        self.future += 1;
+        self.edits = graph.edits();
+        Some(diff.tick)
+    }
+}
@@ -0,0 +1,31 @@
+//! Headless core of nodular: the graph, the rules and the automaton that steps them.
+//! The raylib editor and midi output live in the binary behind the `gui` and `midi` features.
+
+pub mod active;
+pub mod automaton;
+pub mod compiled;
+pub mod component;
+pub mod cycle;
+pub mod generate;
+pub mod graph;
+pub mod history;
+pub mod lifelike;
+pub mod note;
+pub mod presets;
+pub mod rng;
+pub mod ruletext;
+pub mod stats;
+pub mod undo;
+pub mod vec2;
+
+pub use automaton::{Action, Automaton, Compare, Pattern, Rule, Ruleset, RulesetError, UpdateMode};
+pub use component::Component;
+pub use cycle::Cycle;
+pub use generate::Neighbourhood;
+pub use graph::{Direction, Edge, Graph, Node, NodeId, NodeKind};
+pub use note::Note;
+pub use presets::{Preset, PRESETS};
+pub use ruletext::{parse_ruleset, ParseError};
+pub use stats::RuleStats;
+pub use undo::Edit;
+pub use vec2::Vec2;
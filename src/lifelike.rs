@@ -0,0 +1,154 @@
+//! Rulesets in the notation of Life-like cellular automata, so the classic rule families can run
+//! on any graph.
+//!
+//! `B3/S23` is Conway's Life: a dead node with exactly 3 alive neighbours is born, an alive node
+//! with 2 or 3 survives, everything else dies. The digits are neighbour counts, which on a graph
+//! are the summed edge weights of the alive neighbours like in every other pattern.
+//!
+//! Generations rules add a number of states, `B2/S/3` is Brian's Brain with 3: a node that
+//! doesn't survive goes through the `3 - 2` dying states before it is dead and can be born
+//! again. Dying nodes don't count as alive. The parts can come in any order and the number of
+//! states may be written `C3`. Without letters the order is survival first, `23/3` is Life.
+
+use crate::automaton::{Pattern, Rule, Ruleset};
+use crate::ruletext::ParseError;
+
+const DEAD: u32 = 0;
+const ALIVE: u32 = 1;
+
+/// most states a generations rule can have
+const MAX_STATES: u32 = 256;
+
+/// the parts of the notation
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Born,
+    Survive,
+    States,
+}
+
+impl Ruleset {
+    /// builds the ruleset for `B3/S23`-like notation, states are `dead`, `alive`, `dying1`, ...
+    pub fn life_like(notation: &str) -> Result<Self, ParseError> {
+        let mut born = None;
+        let mut survive = None;
+        let mut states = None;
+        let parts: Vec<&str> = notation.trim().split('/').collect();
+        let lettered = parts
+            .iter()
+            .any(|a| a.starts_with(|a: char| a.is_ascii_alphabetic()));
+        let mut column = 1;
+        for (i, text) in parts.iter().enumerate() {
+            let error = |message: String| Err(ParseError::new(1, column, message));
+            let (part, digits) = match text.chars().next().map(|a| a.to_ascii_uppercase()) {
+                Some('B') => (Part::Born, &text[1..]),
+                Some('S') => (Part::Survive, &text[1..]),
+                Some('C') | Some('G') => (Part::States, &text[1..]),
+                Some(a) if a.is_ascii_alphabetic() => {
+                    return error(format!("expected `B`, `S` or `C`, found `{}`", text))
+                }
+                // unlettered parts are survival, birth and states, except after letters where
+                // only the states can go without one
+                _ => match (lettered, i) {
+                    (false, 0) => (Part::Survive, *text),
+                    (false, 1) => (Part::Born, *text),
+                    (_, 2) => (Part::States, *text),
+                    _ => return error(format!("expected `B`, `S` or `C`, found `{}`", text)),
+                },
+            };
+            let digits_column = column + text.len() - digits.len();
+            if part == Part::States {
+                if states.is_some() {
+                    return error("the number of states is given twice".to_string());
+                }
+                match digits.parse() {
+                    Ok(count) if (2..=MAX_STATES).contains(&count) => states = Some(count),
+                    _ => {
+                        return Err(ParseError::new(
+                            1,
+                            digits_column,
+                            format!(
+                                "expected a number of states from 2 to {}, found `{}`",
+                                MAX_STATES, digits
+                            ),
+                        ))
+                    }
+                }
+            } else {
+                let slot = if part == Part::Born {
+                    &mut born
+                } else {
+                    &mut survive
+                };
+                if slot.is_some() {
+                    return error(format!("`{}` is given twice", text));
+                }
+                let mut counts = vec![];
+                for (j, digit) in digits.chars().enumerate() {
+                    match digit.to_digit(10) {
+                        Some(count) if !counts.contains(&(count as i32)) => {
+                            counts.push(count as i32)
+                        }
+                        _ => {
+                            return Err(ParseError::new(
+                                1,
+                                digits_column + j,
+                                format!("expected a neighbour count, found `{}`", digit),
+                            ))
+                        }
+                    }
+                }
+                // an empty part without a letter, like either side of `/`, gives nothing
+                if !text.is_empty() {
+                    *slot = Some(counts);
+                }
+            }
+            column += text.len() + 1;
+        }
+        if born.is_none() && survive.is_none() {
+            return Err(ParseError::new(
+                1,
+                1,
+                "expected the neighbour counts of `B` or `S`",
+            ));
+        }
+
+        Ok(generations(
+            &born.unwrap_or_default(),
+            &survive.unwrap_or_default(),
+            states.unwrap_or(2),
+        ))
+    }
+}
+
+/// alive neighbours equal to any of `counts`, `None` if there are none
+fn alive_in(counts: &[i32]) -> Option<Pattern> {
+    counts
+        .iter()
+        .map(|number| Pattern::Equal {
+            state: ALIVE,
+            number: *number,
+        })
+        .reduce(|a, b| Pattern::Or(Box::new(a), Box::new(b)))
+}
+
+fn generations(born: &[i32], survive: &[i32], states: u32) -> Ruleset {
+    // after `alive` come the dying states, the last one goes back to `dead`
+    let next = |state: u32| if state + 1 < states { state + 1 } else { DEAD };
+
+    let mut dead = vec![];
+    dead.extend(alive_in(born).map(|a| Rule::new(a, ALIVE)));
+    dead.push(Rule::new(Pattern::Wildcard, DEAD));
+
+    let mut alive = vec![];
+    alive.extend(alive_in(survive).map(|a| Rule::new(a, ALIVE)));
+    alive.push(Rule::new(Pattern::Wildcard, next(ALIVE)));
+
+    let mut rules = vec![dead, alive];
+    let mut names = vec!["dead".to_string(), "alive".to_string()];
+    for state in 2..states {
+        rules.push(vec![Rule::new(Pattern::Wildcard, next(state))]);
+        names.push(format!("dying{}", state - 1));
+    }
+    Ruleset::new(rules, names).expect("every state has a fallback")
+}
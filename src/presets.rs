@@ -0,0 +1,58 @@
+//! Rulesets that come with the editor. They are the files in `rules/`, compiled in so new worlds
+//! can start from them without looking for files.
+
+use crate::automaton::Ruleset;
+use crate::ruletext::ParseError;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// the ruleset in the text format
+    pub source: &'static str,
+}
+
+impl Preset {
+    pub fn ruleset(&self) -> Result<Ruleset, ParseError> {
+        self.source.parse()
+    }
+}
+
+/// the first one is what the editor starts with
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "electron",
+        description: "turns on next to 1 or 2 turned on neighbours",
+        source: include_str!("../rules/electron.rules"),
+    },
+    Preset {
+        name: "wireworld",
+        description: "electrons running along conductors",
+        source: include_str!("../rules/wireworld.rules"),
+    },
+    Preset {
+        name: "brian's brain",
+        description: "fires with 2 firing neighbours, then recovers",
+        source: include_str!("../rules/brians_brain.rules"),
+    },
+    Preset {
+        name: "game of life",
+        description: "B3/S23",
+        source: include_str!("../rules/life.rules"),
+    },
+    Preset {
+        name: "majority vote",
+        description: "takes the opinion most neighbours hold",
+        source: include_str!("../rules/majority.rules"),
+    },
+    Preset {
+        name: "excitable medium",
+        description: "waves of excitation with a refractory period",
+        source: include_str!("../rules/excitable.rules"),
+    },
+];
+
+/// the preset called `name`
+pub fn preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|a| a.name == name)
+}
@@ -0,0 +1,40 @@
+/// Randomness for one step of the automaton.
+///
+/// Every roll is a hash of the seed, the tick, the node and the rule, so there is no generator
+/// state to save: a world replays identically from its seed and tick no matter in which order
+/// nodes are evaluated.
+#[derive(Clone, Copy, Debug)]
+pub struct Rng {
+    seed: u64,
+    tick: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64, tick: u64) -> Self {
+        Self { seed, tick }
+    }
+
+    /// a number in `0.0..1.0` for `rule` of `node` at this tick
+    pub fn roll(&self, node: usize, rule: usize) -> f64 {
+        let mut x = splitmix64(self.seed);
+        x = splitmix64(x ^ self.tick);
+        x = splitmix64(x ^ node as u64);
+        x = splitmix64(x ^ rule as u64);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// fisher yates shuffle, the same for the same seed and tick
+    pub fn shuffle<T>(&self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.roll(i, usize::MAX) * (i + 1) as f64) as usize;
+            items.swap(i, j.min(i));
+        }
+    }
+}
+
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
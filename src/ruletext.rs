@@ -0,0 +1,1041 @@
+//! A small text format for rulesets, so rules can live in plain files.
+//!
+//! ```text
+//! # comments run to the end of the line
+//! electron: electron in {1, 2} -> wire; * -> wire
+//! wire:
+//!     electron in {2, 3} -> electron;
+//!     * -> wire
+//! ```
+//!
+//! Every `name:` starts the rules of a new state, states are numbered in the order they are
+//! declared. A rule is `pattern -> state` and the first matching rule of a state wins, so rules
+//! after `* -> state` are an error.
+//! `pattern -> state @ 0.25` only fires a quarter of the time, otherwise the next rule is tried.
+//!
+//! Nodes also have a few integer registers `r0`, `r1`, ... that patterns can compare
+//! (`r0 >= 4`) and rules can change after the new state: `-> state, r0 += 1, r1 = 0`.
+//! Leaving out the state keeps the current one: `* -> r0 -= 1`.
+//!
+//! Rules can also change the graph once every node has its new state: `spawn(s)` adds a node in
+//! `s` connected both ways, `delete` removes the node, `connect(s)` adds edges to the nodes in `s`
+//! two edges away and `disconnect(s)` removes the edges to neighbours in `s`.
+//! Patterns sum the edge weights of the neighbours in a state: `s = n`, `s > n`, `s < n`,
+//! `s >= n`, `s <= n`, `s in {a, b}`, combined with `&`, `|`, `!` and parentheses.
+//! Numbers may be negative. `*` always matches.
+//!
+//! On irregular graphs relative counts are often more useful: `a > b` holds when there are more
+//! neighbours in `a` than in `b`, `a >= 50%` when at least half of the neighbours are in `a`,
+//! and `degree >= n` when the neighbours add up to at least `n` whatever their state.
+//!
+//! Neighbours are the nodes a node has edges to. `incoming(...)` counts the patterns inside over
+//! the nodes with edges to it instead, `both(...)` over both, and `outgoing(...)` switches back.
+//! `within(k, ...)` counts every node at most `k` edges away and `distance(k, ...)` the nodes
+//! exactly `k` edges away. Those count each node once, edge weights only matter next door.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use crate::automaton::{Action, Compare, Pattern, Rule, Ruleset};
+use crate::graph::{Direction, REGISTERS};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i32),
+    Decimal(f64),
+    Colon,
+    Semi,
+    Arrow,
+    Star,
+    At,
+    Percent,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    Bar,
+    Amp,
+    Bang,
+    Eq,
+    Gth,
+    Lth,
+    Geq,
+    Leq,
+    PlusEq,
+    MinusEq,
+    Eof,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(number) => write!(f, "`{}`", number),
+            Token::Decimal(number) => write!(f, "`{}`", number),
+            Token::Colon => write!(f, "`:`"),
+            Token::Semi => write!(f, "`;`"),
+            Token::Arrow => write!(f, "`->`"),
+            Token::Star => write!(f, "`*`"),
+            Token::At => write!(f, "`@`"),
+            Token::Percent => write!(f, "`%`"),
+            Token::LBrace => write!(f, "`{{`"),
+            Token::RBrace => write!(f, "`}}`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Bar => write!(f, "`|`"),
+            Token::Amp => write!(f, "`&`"),
+            Token::Bang => write!(f, "`!`"),
+            Token::Eq => write!(f, "`=`"),
+            Token::Gth => write!(f, "`>`"),
+            Token::Lth => write!(f, "`<`"),
+            Token::Geq => write!(f, "`>=`"),
+            Token::Leq => write!(f, "`<=`"),
+            Token::PlusEq => write!(f, "`+=`"),
+            Token::MinusEq => write!(f, "`-=`"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn next_is_digit(chars: &std::iter::Peekable<std::str::Chars>) -> bool {
+    let mut ahead = chars.clone();
+    ahead.next();
+    ahead.next().is_some_and(|a| a.is_ascii_digit())
+}
+
+fn tokenize(source: &str) -> Result<Vec<Spanned>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    let mut column = 1;
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut bump = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            c
+        };
+
+        if c.is_whitespace() {
+            bump(&mut chars);
+            continue;
+        }
+        if c == '#' {
+            while chars.peek().is_some_and(|a| *a != '\n') {
+                bump(&mut chars);
+            }
+            continue;
+        }
+
+        let token = if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    name.push(c);
+                    bump(&mut chars);
+                } else {
+                    break;
+                }
+            }
+            Token::Ident(name)
+        } else if c.is_ascii_digit() || c == '-' && next_is_digit(&chars) {
+            let mut digits = String::new();
+            if c == '-' {
+                digits.push(c);
+                bump(&mut chars);
+            }
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() || c == '.' && !digits.contains('.') {
+                    digits.push(c);
+                    bump(&mut chars);
+                } else {
+                    break;
+                }
+            }
+            if digits.contains('.') {
+                Token::Decimal(digits.parse().map_err(|_| {
+                    ParseError::new(
+                        start_line,
+                        start_column,
+                        format!("invalid number `{}`", digits),
+                    )
+                })?)
+            } else {
+                Token::Number(digits.parse().map_err(|_| {
+                    ParseError::new(
+                        start_line,
+                        start_column,
+                        format!("number `{}` is too large", digits),
+                    )
+                })?)
+            }
+        } else {
+            bump(&mut chars);
+            match c {
+                ':' => Token::Colon,
+                ';' => Token::Semi,
+                '*' => Token::Star,
+                '@' => Token::At,
+                '%' => Token::Percent,
+                '{' => Token::LBrace,
+                '}' => Token::RBrace,
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                '|' => Token::Bar,
+                '&' => Token::Amp,
+                '!' => Token::Bang,
+                '=' => Token::Eq,
+                '-' if chars.peek() == Some(&'>') => {
+                    bump(&mut chars);
+                    Token::Arrow
+                }
+                '-' if chars.peek() == Some(&'=') => {
+                    bump(&mut chars);
+                    Token::MinusEq
+                }
+                '+' if chars.peek() == Some(&'=') => {
+                    bump(&mut chars);
+                    Token::PlusEq
+                }
+                '>' | '<' if chars.peek() == Some(&'=') => {
+                    bump(&mut chars);
+                    if c == '>' {
+                        Token::Geq
+                    } else {
+                        Token::Leq
+                    }
+                }
+                '>' => Token::Gth,
+                '<' => Token::Lth,
+                _ => {
+                    return Err(ParseError::new(
+                        start_line,
+                        start_column,
+                        format!("unexpected character `{}`", c),
+                    ))
+                }
+            }
+        };
+        tokens.push(Spanned {
+            token,
+            line: start_line,
+            column: start_column,
+        });
+    }
+    tokens.push(Spanned {
+        token: Token::Eof,
+        line,
+        column,
+    });
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+    names: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Spanned {
+        let token = self.tokens[self.position].clone();
+        if token.token != Token::Eof {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        let token = self.peek();
+        Err(ParseError::new(token.line, token.column, message))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        if self.peek().token == expected {
+            self.next();
+            Ok(())
+        } else {
+            self.error(format!(
+                "expected {}, found {}",
+                expected,
+                self.peek().token
+            ))
+        }
+    }
+
+    fn at_header(&self) -> bool {
+        matches!(self.peek().token, Token::Ident(_))
+            && self.tokens.get(self.position + 1).map(|a| &a.token) == Some(&Token::Colon)
+    }
+
+    fn number(&mut self) -> Result<i32, ParseError> {
+        match self.peek().token {
+            Token::Number(number) => {
+                self.next();
+                Ok(number)
+            }
+            ref other => self.error(format!("expected a number, found {}", other)),
+        }
+    }
+
+    fn state(&mut self) -> Result<u32, ParseError> {
+        match &self.peek().token {
+            Token::Ident(name) => match self.names.iter().position(|a| a == name) {
+                Some(state) => {
+                    self.next();
+                    Ok(state as u32)
+                }
+                None => self.error(format!("unknown state `{}`", name)),
+            },
+            other => self.error(format!("expected a state name, found {}", other)),
+        }
+    }
+
+    fn ruleset(&mut self) -> Result<Ruleset, ParseError> {
+        let mut rules = vec![];
+        while self.peek().token != Token::Eof {
+            if !self.at_header() {
+                return self.error(format!("expected `state:`, found {}", self.peek().token));
+            }
+            let header = self.next();
+            self.next();
+
+            let mut state_rules = vec![];
+            let state = rules.len() as u32;
+            loop {
+                if state_rules.last().is_some_and(Rule::is_fallback) {
+                    return self.error("this rule never fires, the `*` rule before it always does");
+                }
+                state_rules.push(self.rule(state)?);
+                if self.peek().token == Token::Semi {
+                    self.next();
+                    if self.at_header() || self.peek().token == Token::Eof {
+                        break;
+                    }
+                } else if self.at_header() || self.peek().token == Token::Eof {
+                    break;
+                } else {
+                    return self.error(format!("expected `;`, found {}", self.peek().token));
+                }
+            }
+
+            if !state_rules.iter().any(|a: &Rule| a.is_fallback()) {
+                let name = match header.token {
+                    Token::Ident(name) => name,
+                    _ => unreachable!(),
+                };
+                return Err(ParseError::new(
+                    header.line,
+                    header.column,
+                    format!("state `{}` needs a `* -> state` fallback rule", name),
+                ));
+            }
+            rules.push(state_rules);
+        }
+        // nodes need at least one state to be in
+        if rules.is_empty() {
+            return self.error("expected `state:`, found end of input");
+        }
+        Ruleset::new(rules, self.names.clone()).map_err(|a| ParseError::new(1, 1, a.to_string()))
+    }
+
+    /// `r0`, `r1`, ... unless a state has that name
+    fn register(&self) -> Option<usize> {
+        match &self.peek().token {
+            Token::Ident(name) if !self.names.contains(name) => {
+                let digits = name.strip_prefix('r')?;
+                if digits.is_empty() || !digits.chars().all(|a| a.is_ascii_digit()) {
+                    return None;
+                }
+                digits.parse().ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// consumes a register, checking that nodes have it
+    fn expect_register(&mut self) -> Result<usize, ParseError> {
+        match self.register() {
+            Some(register) if register < REGISTERS => {
+                self.next();
+                Ok(register)
+            }
+            Some(register) => self.error(format!(
+                "nodes only have {} registers, `r{}` does not exist",
+                REGISTERS, register
+            )),
+            None => self.error(format!("expected a register, found {}", self.peek().token)),
+        }
+    }
+
+    /// `spawn`, `delete`, `connect` or `disconnect`, unless a state has that name
+    fn topology_keyword(&self) -> Option<String> {
+        match &self.peek().token {
+            Token::Ident(name)
+                if !self.names.contains(name)
+                    && ["spawn", "delete", "connect", "disconnect"].contains(&name.as_str()) =>
+            {
+                Some(name.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn at_action(&self) -> bool {
+        self.topology_keyword().is_some()
+            || self.register().is_some()
+                && matches!(
+                    self.tokens.get(self.position + 1).map(|a| &a.token),
+                    Some(Token::Eq | Token::PlusEq | Token::MinusEq)
+                )
+    }
+
+    fn action(&mut self) -> Result<Action, ParseError> {
+        if let Some(keyword) = self.topology_keyword() {
+            self.next();
+            if keyword == "delete" {
+                return Ok(Action::Delete);
+            }
+            self.expect(Token::LParen)?;
+            let state = self.state()?;
+            self.expect(Token::RParen)?;
+            return Ok(match keyword.as_str() {
+                "spawn" => Action::Spawn { state },
+                "connect" => Action::Connect { state },
+                _ => Action::Disconnect { state },
+            });
+        }
+
+        let register = self.expect_register()?;
+        let operator = self.next();
+        let number = self.number()?;
+        Ok(match operator.token {
+            Token::PlusEq => Action::Add {
+                register,
+                amount: number,
+            },
+            Token::MinusEq => Action::Add {
+                register,
+                amount: number.wrapping_neg(),
+            },
+            _ => Action::Set {
+                register,
+                value: number,
+            },
+        })
+    }
+
+    /// `state` is the state whose rules are being parsed, rules that only
+    /// change registers keep it
+    fn rule(&mut self, state: u32) -> Result<Rule, ParseError> {
+        let pattern = self.or()?;
+        self.expect(Token::Arrow)?;
+        let mut replacement = None;
+        let mut actions = vec![];
+        loop {
+            if self.at_action() {
+                actions.push(self.action()?);
+            } else if replacement.is_none() && actions.is_empty() {
+                replacement = Some(self.state()?);
+            } else {
+                return self.error(format!(
+                    "expected a register action, found {} (the new state goes first)",
+                    self.peek().token
+                ));
+            }
+            if self.peek().token == Token::Comma {
+                self.next();
+            } else {
+                break;
+            }
+        }
+        let mut rule = Rule::new(pattern, replacement.unwrap_or(state));
+        rule.actions = actions;
+        if self.peek().token == Token::At {
+            self.next();
+            let token = self.next();
+            let probability = match token.token {
+                Token::Decimal(number) => number,
+                Token::Number(number) => number as f64,
+                ref other => {
+                    return Err(ParseError::new(
+                        token.line,
+                        token.column,
+                        format!("expected a probability, found {}", other),
+                    ))
+                }
+            };
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(ParseError::new(
+                    token.line,
+                    token.column,
+                    format!("probability {} is not between 0 and 1", probability),
+                ));
+            }
+            rule = rule.with_probability(probability);
+        }
+        Ok(rule)
+    }
+
+    fn or(&mut self) -> Result<Pattern, ParseError> {
+        let mut pattern = self.and()?;
+        while self.peek().token == Token::Bar {
+            self.next();
+            pattern = Pattern::Or(Box::new(pattern), Box::new(self.and()?));
+        }
+        Ok(pattern)
+    }
+
+    fn and(&mut self) -> Result<Pattern, ParseError> {
+        let mut pattern = self.unary()?;
+        while self.peek().token == Token::Amp {
+            self.next();
+            pattern = Pattern::And(Box::new(pattern), Box::new(self.unary()?));
+        }
+        Ok(pattern)
+    }
+
+    fn unary(&mut self) -> Result<Pattern, ParseError> {
+        match self.peek().token {
+            Token::Bang => {
+                self.next();
+                Ok(Pattern::Not(Box::new(self.unary()?)))
+            }
+            Token::LParen => {
+                self.next();
+                let pattern = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(pattern)
+            }
+            Token::Star => {
+                self.next();
+                Ok(Pattern::Wildcard)
+            }
+            Token::Ident(ref keyword)
+                if self.tokens.get(self.position + 1).map(|a| &a.token) == Some(&Token::LParen) =>
+            {
+                let keyword = keyword.clone();
+                let direction = match keyword.as_str() {
+                    "outgoing" => Some(Direction::Outgoing),
+                    "incoming" => Some(Direction::Incoming),
+                    "both" => Some(Direction::Both),
+                    "within" | "distance" => None,
+                    _ => {
+                        return self.error(format!(
+                            "expected `outgoing`, `incoming`, `both`, `within` or `distance`, found `{}`",
+                            keyword
+                        ))
+                    }
+                };
+                self.next();
+                self.next();
+                let pattern = match direction {
+                    Some(direction) => Pattern::Directed(direction, Box::new(self.or()?)),
+                    None => {
+                        let k = self.peek().clone();
+                        let distance = self.number()?;
+                        if distance < 1 {
+                            return Err(ParseError::new(k.line, k.column, "distances start at 1"));
+                        }
+                        self.expect(Token::Comma)?;
+                        let inner = Box::new(self.or()?);
+                        if keyword == "within" {
+                            Pattern::Within(distance as u32, inner)
+                        } else {
+                            Pattern::AtDistance(distance as u32, inner)
+                        }
+                    }
+                };
+                self.expect(Token::RParen)?;
+                Ok(pattern)
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Pattern, ParseError> {
+        if self.register().is_some() {
+            let register = self.expect_register()?;
+            let operator = self.next();
+            let compare = match operator.token {
+                Token::Eq => Compare::Equal,
+                Token::Gth => Compare::Gth,
+                Token::Lth => Compare::Lth,
+                Token::Geq => Compare::Geq,
+                Token::Leq => Compare::Leq,
+                other => {
+                    return Err(ParseError::new(
+                        operator.line,
+                        operator.column,
+                        format!("expected a comparison, found {}", other),
+                    ))
+                }
+            };
+            return Ok(Pattern::Register {
+                register,
+                compare,
+                number: self.number()?,
+            });
+        }
+
+        if let Token::Ident(keyword) = &self.peek().token {
+            if keyword == "degree" && !self.names.contains(keyword) {
+                self.next();
+                self.expect(Token::Geq)?;
+                return Ok(Pattern::DegreeGeq {
+                    number: self.number()?,
+                });
+            }
+        }
+
+        let state = self.state()?;
+        let operator = self.next();
+
+        // `a > b` compares two states
+        if matches!(operator.token, Token::Gth | Token::Lth)
+            && matches!(self.peek().token, Token::Ident(_))
+        {
+            let other = self.state()?;
+            return Ok(if operator.token == Token::Gth {
+                Pattern::MoreThan { state, other }
+            } else {
+                Pattern::MoreThan {
+                    state: other,
+                    other: state,
+                }
+            });
+        }
+
+        // `a >= 50%`
+        if operator.token == Token::Geq
+            && self.tokens.get(self.position + 1).map(|a| &a.token) == Some(&Token::Percent)
+        {
+            let number = self.peek().clone();
+            let percent = self.number()?;
+            self.next();
+            if !(0..=100).contains(&percent) {
+                return Err(ParseError::new(
+                    number.line,
+                    number.column,
+                    format!("{}% is not between 0% and 100%", percent),
+                ));
+            }
+            return Ok(Pattern::AtLeastPercent {
+                state,
+                percent: percent as u32,
+            });
+        }
+
+        let pattern = match operator.token {
+            Token::Eq => Pattern::Equal {
+                state,
+                number: self.number()?,
+            },
+            Token::Gth => Pattern::Gth {
+                state,
+                number: self.number()?,
+            },
+            Token::Lth => Pattern::Lth {
+                state,
+                number: self.number()?,
+            },
+            Token::Geq => Pattern::Geq {
+                state,
+                number: self.number()?,
+            },
+            Token::Leq => Pattern::Leq {
+                state,
+                number: self.number()?,
+            },
+            Token::Ident(ref keyword) if keyword == "in" => {
+                self.expect(Token::LBrace)?;
+                let mut pattern = Pattern::Equal {
+                    state,
+                    number: self.number()?,
+                };
+                while self.peek().token == Token::Comma {
+                    self.next();
+                    pattern = Pattern::Or(
+                        Box::new(pattern),
+                        Box::new(Pattern::Equal {
+                            state,
+                            number: self.number()?,
+                        }),
+                    );
+                }
+                self.expect(Token::RBrace)?;
+                pattern
+            }
+            other => {
+                return Err(ParseError::new(
+                    operator.line,
+                    operator.column,
+                    format!("expected a comparison, found {}", other),
+                ))
+            }
+        };
+        Ok(pattern)
+    }
+}
+
+/// parses the text format described in the module docs
+pub fn parse_ruleset(source: &str) -> Result<Ruleset, ParseError> {
+    let tokens = tokenize(source)?;
+
+    // states can be referenced before they are declared, so collect the headers first
+    let mut names: Vec<String> = vec![];
+    for pair in tokens.windows(2) {
+        if let (Token::Ident(name), Token::Colon) = (&pair[0].token, &pair[1].token) {
+            if names.contains(name) {
+                return Err(ParseError::new(
+                    pair[0].line,
+                    pair[0].column,
+                    format!("state `{}` is declared twice", name),
+                ));
+            }
+            names.push(name.clone());
+        }
+    }
+
+    Parser {
+        tokens,
+        position: 0,
+        names,
+    }
+    .ruleset()
+}
+
+impl FromStr for Ruleset {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ruleset(s)
+    }
+}
+
+struct PatternDisplay<'a> {
+    pattern: &'a Pattern,
+    names: &'a [String],
+}
+
+impl PatternDisplay<'_> {
+    fn name(&self, state: u32) -> &str {
+        self.names
+            .get(state as usize)
+            .map(|a| a.as_str())
+            .unwrap_or("?")
+    }
+
+    fn child<'b>(&'b self, pattern: &'b Pattern) -> PatternDisplay<'b> {
+        PatternDisplay {
+            pattern,
+            names: self.names,
+        }
+    }
+
+    fn precedence(pattern: &Pattern) -> u8 {
+        match pattern {
+            Pattern::Or(..) => 0,
+            Pattern::And(..) => 1,
+            _ => 2,
+        }
+    }
+
+    fn write_operand(&self, f: &mut fmt::Formatter<'_>, pattern: &Pattern, min: u8) -> fmt::Result {
+        if Self::precedence(pattern) < min {
+            write!(f, "({})", self.child(pattern))
+        } else {
+            write!(f, "{}", self.child(pattern))
+        }
+    }
+}
+
+fn or_leaves<'a>(pattern: &'a Pattern, leaves: &mut Vec<&'a Pattern>) {
+    match pattern {
+        Pattern::Or(a, b) => {
+            or_leaves(a, leaves);
+            or_leaves(b, leaves);
+        }
+        other => leaves.push(other),
+    }
+}
+
+fn equal_set(leaves: &[&Pattern]) -> Option<(u32, Vec<i32>)> {
+    let mut set: Option<(u32, Vec<i32>)> = None;
+    for leaf in leaves {
+        match (leaf, &mut set) {
+            (Pattern::Equal { state, number }, None) => set = Some((*state, vec![*number])),
+            (Pattern::Equal { state, number }, Some((set_state, numbers)))
+                if state == set_state =>
+            {
+                numbers.push(*number)
+            }
+            _ => return None,
+        }
+    }
+    set
+}
+
+impl Display for PatternDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pattern {
+            Pattern::Equal { state, number } => write!(f, "{} = {}", self.name(*state), number),
+            Pattern::Gth { state, number } => write!(f, "{} > {}", self.name(*state), number),
+            Pattern::Lth { state, number } => write!(f, "{} < {}", self.name(*state), number),
+            Pattern::Geq { state, number } => write!(f, "{} >= {}", self.name(*state), number),
+            Pattern::Leq { state, number } => write!(f, "{} <= {}", self.name(*state), number),
+            Pattern::MoreThan { state, other } => {
+                write!(f, "{} > {}", self.name(*state), self.name(*other))
+            }
+            Pattern::AtLeastPercent { state, percent } => {
+                write!(f, "{} >= {}%", self.name(*state), percent)
+            }
+            Pattern::DegreeGeq { number } => write!(f, "degree >= {}", number),
+            Pattern::Register {
+                register,
+                compare,
+                number,
+            } => {
+                let operator = match compare {
+                    Compare::Equal => "=",
+                    Compare::Gth => ">",
+                    Compare::Lth => "<",
+                    Compare::Geq => ">=",
+                    Compare::Leq => "<=",
+                };
+                write!(f, "r{} {} {}", register, operator, number)
+            }
+            Pattern::Or(..) => {
+                let mut leaves = vec![];
+                or_leaves(self.pattern, &mut leaves);
+
+                // a disjunction of counts of the same state reads better as a set
+                if let Some((state, numbers)) = equal_set(&leaves) {
+                    write!(f, "{} in {{", self.name(state))?;
+                    for (i, number) in numbers.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", number)?;
+                    }
+                    write!(f, "}}")
+                } else {
+                    for (i, leaf) in leaves.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, " | ")?;
+                        }
+                        self.write_operand(f, leaf, 1)?;
+                    }
+                    Ok(())
+                }
+            }
+            Pattern::And(a, b) => {
+                self.write_operand(f, a, 1)?;
+                write!(f, " & ")?;
+                self.write_operand(f, b, 2)
+            }
+            Pattern::Not(a) => {
+                write!(f, "!")?;
+                self.write_operand(f, a, 2)
+            }
+            Pattern::Directed(direction, a) => {
+                let keyword = match direction {
+                    Direction::Outgoing => "outgoing",
+                    Direction::Incoming => "incoming",
+                    Direction::Both => "both",
+                };
+                write!(f, "{}({})", keyword, self.child(a))
+            }
+            Pattern::Within(k, a) => write!(f, "within({}, {})", k, self.child(a)),
+            Pattern::AtDistance(k, a) => write!(f, "distance({}, {})", k, self.child(a)),
+            Pattern::Wildcard => write!(f, "*"),
+        }
+    }
+}
+
+/// one rule in the text format, as in `a > 1 -> b, r0 += 1 @ 0.5`
+pub struct RuleDisplay<'a> {
+    rule: &'a Rule,
+    names: &'a [String],
+}
+
+impl Ruleset {
+    /// rule `rule` of `state` in the text format
+    pub fn display_rule(&self, state: usize, rule: usize) -> RuleDisplay<'_> {
+        RuleDisplay {
+            rule: &self.rules[state][rule],
+            names: &self.names,
+        }
+    }
+}
+
+impl Display for RuleDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name_of = |state: u32| {
+            self.names
+                .get(state as usize)
+                .map(|a| a.as_str())
+                .unwrap_or("?")
+        };
+        let rule = self.rule;
+        write!(
+            f,
+            "{} -> {}",
+            PatternDisplay {
+                pattern: &rule.pattern,
+                names: self.names,
+            },
+            name_of(rule.replacement)
+        )?;
+        for action in &rule.actions {
+            match action {
+                // `i32::MIN` has no positive counterpart to subtract
+                Action::Add { register, amount } if *amount < 0 && *amount != i32::MIN => {
+                    write!(f, ", r{} -= {}", register, -amount)?
+                }
+                Action::Add { register, amount } => write!(f, ", r{} += {}", register, amount)?,
+                Action::Set { register, value } => write!(f, ", r{} = {}", register, value)?,
+                Action::Spawn { state } => write!(f, ", spawn({})", name_of(*state))?,
+                Action::Delete => write!(f, ", delete")?,
+                Action::Connect { state } => write!(f, ", connect({})", name_of(*state))?,
+                Action::Disconnect { state } => write!(f, ", disconnect({})", name_of(*state))?,
+            }
+        }
+        if let Some(probability) = rule.probability {
+            write!(f, " @ {}", probability)?;
+        }
+        Ok(())
+    }
+}
+
+/// prints a ruleset back in the text format, one state per line
+impl Display for Ruleset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (state, rules) in self.rules.iter().enumerate() {
+            let name = self.names.get(state).map(|a| a.as_str()).unwrap_or("?");
+            write!(f, "{}:", name)?;
+            for i in 0..rules.len() {
+                if i > 0 {
+                    write!(f, ";")?;
+                }
+                write!(f, " {}", self.display_rule(state, i))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize) {
+        let error = parse_ruleset(source).unwrap_err();
+        (error.line, error.column)
+    }
+
+    #[test]
+    fn printed_rulesets_parse_back_the_same() {
+        for source in [
+            include_str!("../rules/electron.rules"),
+            include_str!("../rules/life.rules"),
+            include_str!("../rules/brians_brain.rules"),
+            "a: !(b >= 2 | a < -1) & b in {1, 3} -> b, r0 -= 2, r3 = -7 @ 0.25; * -> a\n\
+             b: a > b & a >= 50% & degree >= 3 -> a; incoming(within(2, a = 1)) -> a; \
+             r1 <= 4 -> r1 += 1; * -> b, spawn(a), delete, connect(b), disconnect(a)",
+        ] {
+            let printed = parse_ruleset(source).unwrap().to_string();
+            assert_eq!(parse_ruleset(&printed).unwrap().to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn smallest_amount_prints() {
+        for source in ["a: * -> r0 += -2147483648", "a: * -> r0 -= -2147483648"] {
+            let printed = parse_ruleset(source).unwrap().to_string();
+            assert_eq!(printed, "a: * -> a, r0 += -2147483648\n");
+            assert_eq!(parse_ruleset(&printed).unwrap().to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let rules = parse_ruleset("a: a = 1 | a = 2 & !a = 3 -> a; * -> a").unwrap();
+        let equal = |number| Box::new(Pattern::Equal { state: 0, number });
+        assert_eq!(
+            rules.rules[0][0].pattern,
+            Pattern::Or(
+                equal(1),
+                Box::new(Pattern::And(equal(2), Box::new(Pattern::Not(equal(3)))))
+            )
+        );
+        let printed = parse_ruleset("a: (a = 1 | b = 2) & a = 3 -> a; * -> a\nb: * -> b")
+            .unwrap()
+            .to_string();
+        assert!(printed.contains("(a = 1 | b = 2) & a = 3"), "{}", printed);
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        assert_eq!(error("a: * -> b"), (1, 9));
+        assert_eq!(error("a:\n  a > 1 -> a;\n  * -> a; * -> a"), (3, 11));
+        assert_eq!(error("a: a ? 1 -> a; * -> a"), (1, 6));
+        assert_eq!(error("a: a > 1 -> a"), (1, 1));
+        assert_eq!(error("a: * -> a\na: * -> a"), (2, 1));
+        assert_eq!(error("a: * -> a @ 2"), (1, 13));
+        assert_eq!(error("a: * -> a, r9 = 1"), (1, 12));
+    }
+
+    #[test]
+    fn rulesets_need_a_state() {
+        assert!(parse_ruleset("").is_err());
+        assert!(parse_ruleset("# only a comment\n").is_err());
+    }
+}
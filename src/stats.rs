@@ -0,0 +1,126 @@
+//! How often each rule fired, so rules that never fire and the ones doing all the work show up.
+//!
+//! `Automaton::step` counts into `Automaton::stats` while it is `Some`. To count every node it
+//! looks at all of them then, not only the ones that can change, so recording is slower.
+//! Steps taken back stay counted, and aren't counted again when they are taken again.
+
+use crate::automaton::Ruleset;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleStats {
+    /// `counts[state][rule]`
+    counts: Vec<Vec<u64>>,
+    /// steps counted
+    pub steps: u64,
+}
+
+/// one rule in an export
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct RuleCount {
+    pub state: String,
+    pub rule: usize,
+    pub text: String,
+    pub count: u64,
+}
+
+impl RuleStats {
+    pub fn new(ruleset: &Ruleset) -> Self {
+        Self {
+            counts: ruleset.rules.iter().map(|a| vec![0; a.len()]).collect(),
+            steps: 0,
+        }
+    }
+
+    /// whether the counts are for a ruleset shaped like this one
+    pub fn fits(&self, ruleset: &Ruleset) -> bool {
+        self.counts.len() == ruleset.rules.len()
+            && self
+                .counts
+                .iter()
+                .zip(&ruleset.rules)
+                .all(|(a, b)| a.len() == b.len())
+    }
+
+    pub(crate) fn record(&mut self, state: u32, rule: usize) {
+        if let Some(count) = self
+            .counts
+            .get_mut(state as usize)
+            .and_then(|a| a.get_mut(rule))
+        {
+            *count += 1;
+        }
+    }
+
+    pub fn count(&self, state: usize, rule: usize) -> u64 {
+        self.counts
+            .get(state)
+            .and_then(|a| a.get(rule))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// how often any rule fired
+    pub fn total(&self) -> u64 {
+        self.counts.iter().flatten().sum()
+    }
+
+    /// `(state, rule)` of the rules that never fired
+    pub fn unused(&self) -> Vec<(usize, usize)> {
+        let mut unused = vec![];
+        for (state, counts) in self.counts.iter().enumerate() {
+            for (rule, count) in counts.iter().enumerate() {
+                if *count == 0 {
+                    unused.push((state, rule));
+                }
+            }
+        }
+        unused
+    }
+
+    /// every rule with its count, in ruleset order
+    pub fn rows(&self, ruleset: &Ruleset) -> Vec<RuleCount> {
+        let mut rows = vec![];
+        for (state, rules) in ruleset.rules.iter().enumerate() {
+            for rule in 0..rules.len() {
+                rows.push(RuleCount {
+                    state: ruleset.names.get(state).cloned().unwrap_or_default(),
+                    rule,
+                    text: ruleset.display_rule(state, rule).to_string(),
+                    count: self.count(state, rule),
+                });
+            }
+        }
+        rows
+    }
+
+    pub fn to_json(&self, ruleset: &Ruleset) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&serde_json::json!({
+            "steps": self.steps,
+            "rules": self.rows(ruleset),
+        }))
+    }
+
+    /// `state,rule,text,count` with a header line
+    pub fn to_csv(&self, ruleset: &Ruleset) -> String {
+        let mut csv = String::from("state,rule,text,count\n");
+        for row in self.rows(ruleset) {
+            csv += &format!(
+                "{},{},{},{}\n",
+                csv_field(&row.state),
+                row.rule,
+                csv_field(&row.text),
+                row.count
+            );
+        }
+        csv
+    }
+}
+
+/// quotes fields with commas, quotes or line breaks in them
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,390 @@
+//! Undo and redo for edits to the world.
+//!
+//! Every edit is kept as the edit that takes it back, and making an edit gives the one that
+//! takes that back again, so undoing moves edits from the past to the future and redoing moves
+//! them back. Edits name nodes by `NodeId` and removed nodes come back with the ids they had, so
+//! the edits around them still find them. Nodes that are gone since, say removed by a rule, are
+//! skipped. Steps aren't edits, "step back" takes those back.
+
+use std::collections::HashMap;
+
+use crate::automaton::Automaton;
+use crate::component::Component;
+use crate::graph::{Edge, Graph, Node, NodeId};
+use crate::vec2::Vec2;
+
+/// how many edits can be taken back
+pub const LIMIT: usize = 256;
+
+#[derive(Clone, Debug)]
+pub enum Edit {
+    /// removes the nodes and their edges
+    Remove(Vec<NodeId>),
+    /// puts removed nodes and their edges back
+    Restore(Vec<Removed>),
+    /// sets the weight of the edge from `from` to `to`, `None` removes it
+    Edge {
+        from: NodeId,
+        to: NodeId,
+        weight: Option<i32>,
+    },
+    /// changes the settings of nodes
+    Nodes(Vec<Change>),
+    /// swaps in the component definitions
+    Components(Vec<Component>),
+    /// makes the edits in order
+    Many(Vec<Edit>),
+}
+
+/// a removed node, edges between removed nodes are in both of their lists
+#[derive(Clone, Debug)]
+pub struct Removed {
+    pub id: NodeId,
+    /// without edges
+    pub node: Node,
+    pub edges: Vec<(NodeId, i32)>,
+    pub incoming: Vec<(NodeId, i32)>,
+}
+
+/// a node going from `from` to `to`. only the fields that differ between them are written, so
+/// moving a node back doesn't take back the states it stepped to since
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub id: NodeId,
+    pub from: Node,
+    pub to: Node,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UndoStack {
+    past: Vec<Edit>,
+    future: Vec<Edit>,
+}
+
+impl Edit {
+    /// whether the edit changes nothing
+    fn is_empty(&self) -> bool {
+        match self {
+            Edit::Remove(a) => a.is_empty(),
+            Edit::Restore(a) => a.is_empty(),
+            Edit::Nodes(a) => a.is_empty(),
+            Edit::Many(a) => a.iter().all(Edit::is_empty),
+            Edit::Edge { .. } | Edit::Components(_) => false,
+        }
+    }
+}
+
+impl Removed {
+    fn new(graph: &Graph, node: usize) -> Self {
+        let edges = |edges: &[Edge]| edges.iter().map(|a| (graph.id(a.to), a.weight)).collect();
+        Self {
+            id: graph.id(node),
+            node: settings(&graph.nodes[node]),
+            edges: edges(&graph.nodes[node].edges),
+            incoming: edges(&graph.nodes[node].incoming),
+        }
+    }
+}
+
+impl Change {
+    fn apply(&self, node: &mut Node) {
+        let (from, to) = (&self.from, &self.to);
+        if from.read != to.read {
+            node.read = to.read;
+        }
+        if from.write != to.write {
+            node.write = to.write;
+        }
+        if from.position != to.position {
+            node.position = to.position;
+        }
+        if from.note != to.note {
+            node.note = to.note.clone();
+        }
+        if (from.period, from.phase) != (to.period, to.phase) {
+            node.period = to.period;
+            node.phase = to.phase;
+        }
+        if from.registers != to.registers {
+            node.registers = to.registers;
+        }
+        if from.pinned != to.pinned {
+            node.pinned = to.pinned;
+        }
+        if from.kind != to.kind {
+            node.kind = to.kind;
+        }
+        if from.member != to.member {
+            node.member = to.member;
+        }
+    }
+
+    fn reversed(self) -> Self {
+        Self {
+            id: self.id,
+            from: self.to,
+            to: self.from,
+        }
+    }
+}
+
+impl UndoStack {
+    fn push(&mut self, edit: Edit) {
+        if edit.is_empty() {
+            return;
+        }
+        self.future.clear();
+        self.past.push(edit);
+        if self.past.len() > LIMIT {
+            self.past.remove(0);
+        }
+    }
+}
+
+impl Automaton {
+    /// makes `edit` and remembers how to take it back
+    pub fn edit(&mut self, edit: Edit) {
+        let undo = self.apply(edit);
+        self.undo_stack.push(undo);
+    }
+
+    /// remembers `undo` as the way to take back an edit that was made without `edit`
+    pub fn record(&mut self, undo: Edit) {
+        self.undo_stack.push(undo);
+    }
+
+    /// changes `nodes` with `f` as one edit. nodes `f` would put in a state the rules don't have
+    /// are left as they were, `false` if there were any
+    pub fn edit_nodes(&mut self, nodes: &[usize], mut f: impl FnMut(&mut Node)) -> bool {
+        let states = self.rules().rules.len() as u32;
+        let mut changes = vec![];
+        let mut kept = true;
+        for &node in nodes {
+            let from = settings(&self.graph.nodes[node]);
+            f(&mut self.graph.nodes[node]);
+            let change = Change {
+                id: self.graph.id(node),
+                from: settings(&self.graph.nodes[node]),
+                to: from,
+            };
+            if change.from.states().any(|a| a >= states) {
+                change.apply(&mut self.graph.nodes[node]);
+                kept = false;
+                continue;
+            }
+            if change.from == change.to {
+                continue;
+            }
+            changes.push(change);
+            self.wake(node);
+        }
+        self.record(Edit::Nodes(changes));
+        kept
+    }
+
+    /// remembers that `nodes` were moved from where `from` says, one edit however long the
+    /// drag took. `from` holds the positions of all nodes
+    pub fn record_move(&mut self, nodes: &[usize], from: &[Vec2]) {
+        let changes: Vec<Change> = nodes
+            .iter()
+            .filter_map(|&a| Some((a, *from.get(a)?)))
+            .filter(|&(a, from)| self.graph.nodes[a].position != from)
+            .map(|(node, from)| {
+                let current = settings(&self.graph.nodes[node]);
+                let mut before = current.clone();
+                before.position = from;
+                Change {
+                    id: self.graph.id(node),
+                    from: current,
+                    to: before,
+                }
+            })
+            .collect();
+        self.record(Edit::Nodes(changes));
+    }
+
+    /// makes whatever `f` does to the graph and components one edit. taking it back only
+    /// undoes what `f` changed, not the steps taken since
+    pub fn edit_world<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let graph = self.graph.clone();
+        let components = self.components.clone();
+        let result = f(self);
+        let mut undo = vec![];
+        if self.components != components {
+            undo.push(Edit::Components(components));
+        }
+        undo.extend(self.diff(&graph));
+        self.record(Edit::Many(undo));
+        result
+    }
+
+    /// the edits that take the graph back to `old`: removing the nodes added since, putting
+    /// back the removed ones and setting the edges and settings of the others
+    fn diff(&self, old: &Graph) -> Vec<Edit> {
+        let added = (0..self.graph.nodes.len())
+            .map(|a| self.graph.id(a))
+            .filter(|a| old.index(*a).is_none())
+            .collect();
+        let removed = (0..old.nodes.len())
+            .filter(|a| self.graph.index(old.id(*a)).is_none())
+            .map(|a| Removed::new(old, a))
+            .collect();
+
+        let mut edges = vec![];
+        let mut changes = vec![];
+        for (before, node) in old.nodes.iter().enumerate() {
+            let id = old.id(before);
+            let Some(after) = self.graph.index(id) else {
+                continue;
+            };
+            let weights = |graph: &Graph, node: &Node| -> HashMap<NodeId, i32> {
+                node.edges
+                    .iter()
+                    .map(|a| (graph.id(a.to), a.weight))
+                    .collect()
+            };
+            let was = weights(old, node);
+            let now = weights(&self.graph, &self.graph.nodes[after]);
+            // edges to added and removed nodes come and go with them
+            for (&to, &weight) in &was {
+                if self.graph.index(to).is_some() && now.get(&to) != Some(&weight) {
+                    edges.push(Edit::Edge {
+                        from: id,
+                        to,
+                        weight: Some(weight),
+                    });
+                }
+            }
+            for &to in now.keys() {
+                if old.index(to).is_some() && !was.contains_key(&to) {
+                    edges.push(Edit::Edge {
+                        from: id,
+                        to,
+                        weight: None,
+                    });
+                }
+            }
+            let from = settings(&self.graph.nodes[after]);
+            let to = settings(node);
+            if from != to {
+                changes.push(Change { id, from, to });
+            }
+        }
+
+        let mut undo = vec![Edit::Remove(added), Edit::Restore(removed)];
+        undo.extend(edges);
+        undo.push(Edit::Nodes(changes));
+        undo
+    }
+
+    /// takes back the last edit, false if there was none
+    pub fn undo(&mut self) -> bool {
+        let Some(edit) = self.undo_stack.past.pop() else {
+            return false;
+        };
+        let redo = self.apply(edit);
+        self.undo_stack.future.push(redo);
+        true
+    }
+
+    /// makes the last edit that was taken back again, false if there was none
+    pub fn redo(&mut self) -> bool {
+        let Some(edit) = self.undo_stack.future.pop() else {
+            return false;
+        };
+        let undo = self.apply(edit);
+        self.undo_stack.past.push(undo);
+        true
+    }
+
+    /// makes `edit` and returns the edit that takes it back
+    fn apply(&mut self, edit: Edit) -> Edit {
+        match edit {
+            Edit::Remove(ids) => {
+                let removed = ids
+                    .iter()
+                    .filter_map(|a| self.graph.index(*a))
+                    .map(|node| Removed::new(&self.graph, node))
+                    .collect();
+                for id in ids {
+                    if let Some(node) = self.graph.index(id) {
+                        self.graph.remove_node(node);
+                    }
+                }
+                Edit::Restore(removed)
+            }
+            Edit::Restore(removed) => {
+                // the new ids of nodes whose slots were taken
+                let mut moved = HashMap::new();
+                let mut ids = vec![];
+                for a in &removed {
+                    let id = self.graph.restore_node(a.id, a.node.clone());
+                    if id != a.id {
+                        moved.insert(a.id, id);
+                    }
+                    ids.push(id);
+                }
+                let index = |graph: &Graph, id: NodeId| graph.index(*moved.get(&id).unwrap_or(&id));
+                for (a, &id) in removed.iter().zip(&ids) {
+                    let node = self.graph.index(id).unwrap();
+                    for &(to, weight) in &a.edges {
+                        if let Some(to) = index(&self.graph, to) {
+                            self.graph.add_weighted_edge(node, to, weight);
+                        }
+                    }
+                    for &(from, weight) in &a.incoming {
+                        if let Some(from) = index(&self.graph, from) {
+                            self.graph.add_weighted_edge(from, node, weight);
+                        }
+                    }
+                }
+                Edit::Remove(ids)
+            }
+            Edit::Edge { from, to, weight } => {
+                let (Some(u), Some(v)) = (self.graph.index(from), self.graph.index(to)) else {
+                    return Edit::Edge { from, to, weight };
+                };
+                let old = self.graph.edge_weight(u, v);
+                match (old, weight) {
+                    (Some(_), Some(weight)) => self.graph.set_edge_weight(u, v, weight),
+                    (None, Some(weight)) => {
+                        self.graph.add_weighted_edge(u, v, weight);
+                    }
+                    (Some(_), None) => self.graph.remove_edge(u, v),
+                    (None, None) => {}
+                }
+                Edit::Edge {
+                    from,
+                    to,
+                    weight: old,
+                }
+            }
+            Edit::Nodes(changes) => {
+                for change in &changes {
+                    if let Some(node) = self.graph.index(change.id) {
+                        change.apply(&mut self.graph.nodes[node]);
+                        self.wake(node);
+                    }
+                }
+                Edit::Nodes(changes.into_iter().map(Change::reversed).collect())
+            }
+            Edit::Components(components) => {
+                Edit::Components(std::mem::replace(&mut self.components, components))
+            }
+            Edit::Many(edits) => {
+                let mut undo: Vec<Edit> = edits.into_iter().map(|a| self.apply(a)).collect();
+                undo.reverse();
+                Edit::Many(undo)
+            }
+        }
+    }
+}
+
+/// `node` without its edges, what `Change` and `Removed` keep
+fn settings(node: &Node) -> Node {
+    Node {
+        edges: vec![],
+        incoming: vec![],
+        ..node.clone()
+    }
+}
@@ -1,15 +1,16 @@
 use serde::{Deserialize, Serialize};
 use std::ops;
-use std::ops::{Deref, DerefMut};
 
-use raylib::prelude::*;
+#[cfg(feature = "gui")]
+use raylib::prelude::Vector2;
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Vec2 {
     pub x: f32,
     pub y: f32,
 }
 
+#[cfg(feature = "gui")]
 impl Into<Vector2> for Vec2 {
     fn into(self) -> Vector2 {
         Vector2 {
@@ -19,6 +20,7 @@ impl Into<Vector2> for Vec2 {
     }
 }
 
+#[cfg(feature = "gui")]
 impl From<Vector2> for Vec2 {
     fn from(value: Vector2) -> Self {
         Self {
@@ -0,0 +1,18 @@
+use nodular::{Automaton, Graph, Node, Ruleset, Vec2};
+
+#[test]
+fn nodes_added_after_a_step_can_be_edited() {
+    let rules: Ruleset = "off: on >= 1 -> on; * -> off\non: * -> off"
+        .parse()
+        .unwrap();
+    let mut graph = Graph::new();
+    graph.add_node(Node::new(0, 0, vec![], Vec2::zero()));
+    let mut automaton = Automaton::new(rules, graph);
+    automaton.step();
+    automaton
+        .graph
+        .add_node(Node::new(0, 0, vec![], Vec2::zero()));
+    automaton.edit_nodes(&[1], |node| node.write = 1);
+    automaton.step();
+    assert_eq!(automaton.graph.nodes[1].write, 0);
+}
@@ -0,0 +1,42 @@
+use nodular::{Automaton, Graph, Node, Ruleset, Vec2};
+
+fn automaton(rules: &str, nodes: usize) -> Automaton {
+    let rules: Ruleset = rules.parse().unwrap();
+    let mut graph = Graph::new();
+    for _ in 0..nodes {
+        graph.add_node(Node::new(0, 0, vec![], Vec2::zero()));
+    }
+    let mut automaton = Automaton::new(rules, graph);
+    automaton.detect_cycles(true);
+    automaton
+}
+
+#[test]
+fn unchanged_states_settle() {
+    let mut automaton = automaton("off: * -> off", 3);
+    for _ in 0..5 {
+        automaton.step();
+    }
+    assert!(automaton.cycle().is_some_and(|a| a.is_fixed_point()));
+}
+
+#[test]
+fn counting_registers_dont_settle() {
+    let mut automaton = automaton("off: * -> r0 += 1", 3);
+    for _ in 0..5 {
+        automaton.step();
+    }
+    assert_eq!(automaton.graph.nodes[0].registers[0], 5);
+    assert_eq!(automaton.cycle(), None);
+}
+
+#[test]
+fn clock_phase_is_part_of_the_state() {
+    // held for a tick by the clock divider, which isn't a fixed point
+    let mut automaton = automaton("on: * -> off\noff: * -> on", 1);
+    automaton.graph.nodes[0].period = 2;
+    for _ in 0..8 {
+        automaton.step();
+    }
+    assert_eq!(automaton.cycle().map(|a| a.period), Some(4));
+}